@@ -0,0 +1,145 @@
+//! The `--buffer-size` option, for big ranges. stdout is line buffered, so
+//! writing CracklePop to it directly means a `write(2)` for every single line,
+//! which is the `print!` overhead the main module docs keep running into.
+//! Putting a `BufWriter` in front batches those into one write per buffer
+//! full, with a single flush at the end.
+//!
+//! Run with `crackle_pop --buffer-size BYTES [--end N]`, writing `1..=N`
+//! (100 by default). A buffer size of 0 turns the buffering off.
+//!
+//! For `--end 10000000` into /dev/null, 76 MB of output, from a release
+//! build, over a couple of runs:
+//!
+//! ```text
+//! --buffer-size 0        2.14-2.28s
+//! --buffer-size 8192     0.09-0.11s
+//! --buffer-size 65536    0.10s
+//! ```
+//!
+//! So about 20x, and past a few KiB the size stops mattering.
+
+use std::io::{self, BufWriter, Write};
+use std::process;
+
+use crate::engine;
+
+#[derive(Debug, PartialEq)]
+struct Options {
+    buffer_size: usize,
+    end: u64,
+}
+
+fn parse_args(args: &[String]) -> Result<Options, String> {
+    let mut buffer_size = None;
+    let mut end = 100;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--buffer-size" => {
+                let value = args.next().ok_or("--buffer-size needs a value")?;
+                buffer_size = Some(
+                    value
+                        .parse()
+                        .map_err(|_| format!("invalid --buffer-size value: {}", value))?,
+                );
+            }
+            "--end" => {
+                let value = args.next().ok_or("--end needs a value")?;
+                end = value
+                    .parse()
+                    .map_err(|_| format!("invalid --end value: {}", value))?;
+            }
+            other => return Err(format!("unknown argument: {}", other)),
+        }
+    }
+    Ok(Options {
+        buffer_size: buffer_size.ok_or("--buffer-size is required")?,
+        end,
+    })
+}
+
+/// Writes `1..=end` to `out` through a `BufWriter` of `buffer_size` bytes.
+fn write_buffered<W: Write>(out: W, buffer_size: usize, end: u64) -> io::Result<()> {
+    let mut out = BufWriter::with_capacity(buffer_size, out);
+    engine::crackle_pop_into(1..=end, &mut out, true)?;
+    out.flush()
+}
+
+pub fn main(args: &[String]) {
+    let options = match parse_args(args) {
+        Ok(options) => options,
+        Err(err) => {
+            eprintln!("{}", err);
+            eprintln!("usage: crackle_pop --buffer-size BYTES [--end N]");
+            process::exit(2);
+        }
+    };
+
+    let stdout = io::stdout();
+    if let Err(err) = write_buffered(stdout.lock(), options.buffer_size, options.end) {
+        eprintln!("{}", err);
+        process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn parses_buffer_size_and_end() {
+        assert_eq!(
+            parse_args(&args(&["--buffer-size", "4096"])),
+            Ok(Options {
+                buffer_size: 4096,
+                end: 100
+            })
+        );
+        assert_eq!(
+            parse_args(&args(&["--end", "15", "--buffer-size", "0"])),
+            Ok(Options {
+                buffer_size: 0,
+                end: 15
+            })
+        );
+        assert!(parse_args(&args(&[])).is_err());
+        assert!(parse_args(&args(&["--buffer-size", "lots"])).is_err());
+        assert!(parse_args(&args(&["--buffer-size", "1", "--fast"])).is_err());
+    }
+
+    /// Counts the writes reaching it, to show the batching.
+    #[derive(Default)]
+    struct CountingSink(Vec<u8>, usize);
+
+    impl Write for &mut CountingSink {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.1 += 1;
+            self.0.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn buffering_batches_the_writes() {
+        let mut expected = Vec::new();
+        engine::crackle_pop_into(1..=1000, &mut expected, true).unwrap();
+
+        let mut unbuffered = CountingSink::default();
+        write_buffered(&mut unbuffered, 0, 1000).unwrap();
+        let mut buffered = CountingSink::default();
+        write_buffered(&mut buffered, 4096, 1000).unwrap();
+
+        assert_eq!(unbuffered.0, expected);
+        assert_eq!(buffered.0, expected);
+        assert_eq!(buffered.1, expected.len().div_ceil(4096));
+        assert!(unbuffered.1 >= 1000);
+    }
+}
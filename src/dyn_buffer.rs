@@ -0,0 +1,132 @@
+//! Picking a buffer size at runtime. `ArrayBuffer`'s size is part of its type,
+//! so this wraps a few common sizes in an enum instead, and falls back to a
+//! `Vec` for anything bigger. Small outputs keep the stack speed, without
+//! having to pick one size for every caller (see `ARRAY_BUFFER_SIZE` for why
+//! oversizing hurts).
+
+use std::io::{self, Write};
+
+use crate::ArrayBuffer;
+
+// Keeping the buffers inline, on the stack, is the whole point.
+#[allow(clippy::large_enum_variant)]
+#[derive(Debug, Clone)]
+pub enum DynBuffer {
+    Small(ArrayBuffer<u8, 256>),
+    Medium(ArrayBuffer<u8, 1024>),
+    Large(ArrayBuffer<u8, 4096>),
+    Heap(Vec<u8>),
+}
+
+/// Runs `$body` with `$buf` bound to whichever `ArrayBuffer` is inside, or
+/// `$heap` for the `Vec`.
+macro_rules! dispatch {
+    ($self:expr, $buf:ident => $body:expr, $vec:ident => $heap:expr) => {
+        match $self {
+            DynBuffer::Small($buf) => $body,
+            DynBuffer::Medium($buf) => $body,
+            DynBuffer::Large($buf) => $body,
+            DynBuffer::Heap($vec) => $heap,
+        }
+    };
+}
+
+impl DynBuffer {
+    /// The smallest buffer that fits `expected` bytes, e.g. from
+    /// `exact_bytes`.
+    pub fn for_len(expected: usize) -> Self {
+        match expected {
+            0..=256 => DynBuffer::Small(ArrayBuffer::new()),
+            257..=1024 => DynBuffer::Medium(ArrayBuffer::new()),
+            1025..=4096 => DynBuffer::Large(ArrayBuffer::new()),
+            _ => DynBuffer::Heap(Vec::with_capacity(expected)),
+        }
+    }
+
+    pub fn as_slice(&self) -> &[u8] {
+        dispatch!(self, buf => buf.as_slice(), vec => vec)
+    }
+
+    pub fn len(&self) -> usize {
+        self.as_slice().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Appends `bytes`. Unlike `ArrayBuffer` this never panics: if they don't
+    /// fit, everything moves over to the heap first.
+    pub fn push_buf(&mut self, bytes: &[u8]) {
+        let fits = dispatch!(&*self, buf => buf.has_room_for(bytes.len()), _vec => true);
+        if !fits {
+            let mut vec = Vec::with_capacity(self.len() + bytes.len());
+            vec.extend_from_slice(self.as_slice());
+            *self = DynBuffer::Heap(vec);
+        }
+        dispatch!(self, buf => buf.push_buf(bytes), vec => vec.extend_from_slice(bytes))
+    }
+
+    /// Writes everything to `out` and empties the buffer, keeping its size.
+    /// Like `ArrayBuffer::write_all_to`, a failed write leaves it as it was.
+    pub fn write_all_to<W: Write>(&mut self, out: &mut W) -> io::Result<()> {
+        dispatch!(self, buf => buf.write_all_to(out), vec => {
+            out.write_all(vec)?;
+            vec.clear();
+            Ok(())
+        })
+    }
+}
+
+impl Write for DynBuffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.push_buf(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::crackle_pop_into;
+
+    #[test]
+    fn for_len_picks_the_smallest_fit() {
+        assert!(matches!(DynBuffer::for_len(0), DynBuffer::Small(_)));
+        assert!(matches!(DynBuffer::for_len(256), DynBuffer::Small(_)));
+        assert!(matches!(
+            DynBuffer::for_len(crate::exact_bytes(1, 100)),
+            DynBuffer::Medium(_)
+        ));
+        assert!(matches!(DynBuffer::for_len(4096), DynBuffer::Large(_)));
+        assert!(matches!(DynBuffer::for_len(4097), DynBuffer::Heap(_)));
+    }
+
+    #[test]
+    fn spills_onto_the_heap_instead_of_panicking() {
+        let mut expected = Vec::new();
+        crackle_pop_into(1..=1000, &mut expected, true).unwrap();
+
+        let mut buf = DynBuffer::for_len(10);
+        crackle_pop_into(1..=1000, &mut buf, true).unwrap();
+        assert!(matches!(buf, DynBuffer::Heap(_)));
+        assert_eq!(buf.as_slice(), &expected[..]);
+
+        let mut out = Vec::new();
+        buf.write_all_to(&mut out).unwrap();
+        assert_eq!(out, expected);
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn stays_on_the_stack_when_sized_right() {
+        let mut buf = DynBuffer::for_len(crate::exact_bytes(1, 100));
+        crackle_pop_into(1..=100, &mut buf, true).unwrap();
+        assert!(matches!(buf, DynBuffer::Medium(_)));
+        assert_eq!(buf.len(), crate::exact_bytes(1, 100));
+    }
+}
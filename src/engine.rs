@@ -0,0 +1,1369 @@
+//! The unified CracklePop entry point. Unlike the many variants in the main
+//! module, which each hardcode `1..=100` and their own output destination, this
+//! works over any range of numbers and writes into any `Write` sink.
+//!
+//! The variants used to disagree on whether the output ends in a newline:
+//!
+//! - `crackle_pop` (in both the main module and `rc_sub`) and
+//!   `rc_sub::crackle_pop_fast` `.trim()`med their output, so there was no
+//!   trailing newline.
+//! - `crackle_pop_hardcoded`, `crackle_pop_faster_utf8`, all the arraybuf
+//!   variants and the `ext`/`fastest` variants end every line, the last one
+//!   included, with a newline.
+//! - `crackle_pop_vec_minimal_vars` pushed a newline after every line and then
+//!   handed the buffer to `println!`, so it actually ended in two.
+//!
+//! They now all end every line with a newline, matching
+//! `tests/crackle_pop_1_100.txt`. Here the caller explicitly chooses with
+//! `trailing_newline`.
+
+use std::convert::TryFrom;
+use std::io::{self, Read, Write};
+use std::ops::{Range, RangeInclusive};
+
+use crate::ArrayBuffer;
+
+const CRACKLE: &[u8] = b"Crackle";
+const POP: &[u8] = b"Pop";
+const CRACKLE_POP: &[u8] = b"CracklePop";
+
+/// What a number turns into under classic CracklePop. The discriminants are
+/// laid out so that the Crackle bit is 1 and the Pop bit is 2, which lets the
+/// SIMD classifier build categories by OR-ing comparison masks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Category {
+    Number = 0,
+    Crackle = 1,
+    Pop = 2,
+    CracklePop = 3,
+}
+
+impl Category {
+    /// 0 is a multiple of both 3 and 5, so it's `CracklePop` rather than a
+    /// number. Classic CracklePop starts at 1 and never asks, but a range
+    /// starting at 0 does, and taking the arithmetic at its word is the least
+    /// surprising answer.
+    pub fn of(n: u64) -> Self {
+        match (n.is_multiple_of(3), n.is_multiple_of(5)) {
+            (true, true) => Category::CracklePop,
+            (true, false) => Category::Crackle,
+            (false, true) => Category::Pop,
+            (false, false) => Category::Number,
+        }
+    }
+
+    /// The word this category is written as, or `None` for a plain number.
+    /// The one place the words live, so every output format agrees on them.
+    pub fn word(self) -> Option<&'static [u8]> {
+        match self {
+            Category::Number => None,
+            Category::Crackle => Some(CRACKLE),
+            Category::Pop => Some(POP),
+            Category::CracklePop => Some(CRACKLE_POP),
+        }
+    }
+
+    /// Writes what `n` turns into, given that this is its category: the word,
+    /// or `n` itself through `write_u64_as_utf8`. No newline.
+    pub fn render<W: Write>(self, n: u64, buf: &mut W) -> io::Result<()> {
+        match self.word() {
+            Some(word) => buf.write_all(word),
+            None => crate::write_u64_as_utf8(n, buf),
+        }
+    }
+}
+
+/// Every number in `range` along with its category, for building on the
+/// classification without any of the rendering.
+pub fn classify(range: RangeInclusive<u64>) -> impl Iterator<Item = (u64, Category)> {
+    range.map(|n| (n, Category::of(n)))
+}
+
+/// How many numbers in `range` satisfy `pred`.
+pub fn count_matching(range: RangeInclusive<u64>, pred: impl Fn(u64) -> bool) -> usize {
+    range.filter(|&n| pred(n)).count()
+}
+
+/// How many numbers in `range` come out as just Crackle, so not CracklePop.
+pub fn count_crackle(range: RangeInclusive<u64>) -> usize {
+    count_matching(range, |n| Category::of(n) == Category::Crackle)
+}
+
+/// How many numbers in `range` come out as just Pop, so not CracklePop.
+pub fn count_pop(range: RangeInclusive<u64>) -> usize {
+    count_matching(range, |n| Category::of(n) == Category::Pop)
+}
+
+/// How many numbers in `range` come out as CracklePop.
+pub fn count_cracklepop(range: RangeInclusive<u64>) -> usize {
+    count_matching(range, |n| Category::of(n) == Category::CracklePop)
+}
+
+/// How many numbers in `range` are written out as themselves.
+pub fn count_numbers(range: RangeInclusive<u64>) -> usize {
+    count_matching(range, |n| Category::of(n) == Category::Number)
+}
+
+/// Writes the CracklePop line for every number in `range` to `out`. Lines are
+/// separated by newlines, and `trailing_newline` decides whether the last line
+/// gets one as well.
+///
+/// To write into a borrowed `&mut [u8]`, wrap it in an `io::Cursor`, which
+/// keeps track of the position. If the slice fills up, this fails with a
+/// `WriteZero` error, and the slice holds the output up to that point.
+pub fn crackle_pop_into<W: Write>(
+    range: RangeInclusive<u64>,
+    out: &mut W,
+    trailing_newline: bool,
+) -> io::Result<()> {
+    let mut first = true;
+    for n in range {
+        // Writing the separator *before* each line means we never have to
+        // know which line is the last one.
+        if !first {
+            out.write_all(b"\n")?;
+        }
+        first = false;
+        Category::of(n).render(n, out)?;
+    }
+
+    if trailing_newline && !first {
+        out.write_all(b"\n")?;
+    }
+    Ok(())
+}
+
+/// Method syntax for `crackle_pop_into` on ranges of numbers, with the
+/// trailing newline:
+///
+/// ```ignore
+/// (1..=100).crackle_pop_into(&mut out)?;
+/// ```
+pub trait CracklePop {
+    fn crackle_pop_into<W: Write>(self, out: &mut W) -> io::Result<()>;
+}
+
+impl CracklePop for RangeInclusive<u64> {
+    fn crackle_pop_into<W: Write>(self, out: &mut W) -> io::Result<()> {
+        crackle_pop_into(self, out, true)
+    }
+}
+
+impl CracklePop for Range<u64> {
+    fn crackle_pop_into<W: Write>(self, out: &mut W) -> io::Result<()> {
+        match self.end.checked_sub(1) {
+            Some(last) => crackle_pop_into(self.start..=last, out, true),
+            // `..0` is empty, and there's no `..=` to turn it into.
+            None => Ok(()),
+        }
+    }
+}
+
+/// Lazily yields the bytes of CracklePop over a range, one line rendered at a
+/// time, trailing newline included. Where `crackle_pop_into` needs somewhere
+/// to write, this can be collected into any container, taken from or skipped
+/// over, or streamed through `io::copy` since it also implements `Read`.
+#[derive(Debug, Clone)]
+pub struct CracklePopIterator {
+    numbers: RangeInclusive<u64>,
+    /// The current line and its newline. 20 digits is enough for any u64.
+    line: [u8; 21],
+    len: usize,
+    /// How much of `line` has been yielded so far.
+    at: usize,
+}
+
+impl CracklePopIterator {
+    pub fn new(range: RangeInclusive<u64>) -> Self {
+        CracklePopIterator {
+            numbers: range,
+            line: [0; 21],
+            len: 0,
+            at: 0,
+        }
+    }
+
+    /// Renders the next number's line into `line`, or returns `false` if the
+    /// range is exhausted.
+    fn next_line(&mut self) -> bool {
+        let n = match self.numbers.next() {
+            Some(n) => n,
+            None => return false,
+        };
+        self.len = render_line(n, &mut self.line);
+        self.at = 0;
+        true
+    }
+}
+
+/// Renders the line for `n` and its newline into the start of `line`,
+/// returning its length.
+fn render_line(n: u64, line: &mut [u8; 21]) -> usize {
+    let len = match Category::of(n).word() {
+        Some(word) => {
+            line[..word.len()].copy_from_slice(word);
+            word.len()
+        }
+        None => crate::format_u64_into(n, line).len(),
+    };
+    line[len] = b'\n';
+    len + 1
+}
+
+impl Iterator for CracklePopIterator {
+    type Item = u8;
+
+    fn next(&mut self) -> Option<u8> {
+        if self.at == self.len && !self.next_line() {
+            return None;
+        }
+        let byte = self.line[self.at];
+        self.at += 1;
+        Some(byte)
+    }
+
+    /// Exact, unless there are more than `usize::MAX` bytes left, in which
+    /// case the lower bound saturates and there's no upper one.
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let current = self.len - self.at;
+        let rest = if self.numbers.is_empty() {
+            Some(0)
+        } else {
+            crate::checked_exact_bytes(*self.numbers.start(), *self.numbers.end())
+        };
+        match rest.and_then(|rest| rest.checked_add(current)) {
+            Some(remaining) => (remaining, Some(remaining)),
+            None => (usize::MAX, None),
+        }
+    }
+}
+
+/// Copies whole runs of the current line at a time rather than going byte by
+/// byte through `next`.
+impl Read for CracklePopIterator {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut written = 0;
+        while written < buf.len() {
+            if self.at == self.len && !self.next_line() {
+                break;
+            }
+            let pending = &self.line[self.at..self.len];
+            let n = pending.len().min(buf.len() - written);
+            buf[written..written + n].copy_from_slice(&pending[..n]);
+            self.at += n;
+            written += n;
+        }
+        Ok(written)
+    }
+}
+
+/// Lazily yields CracklePop over a range a whole line at a time, without the
+/// newline, where `CracklePopIterator` goes byte by byte. Iterating allocates
+/// a `String` per line; `next_into` reuses one instead.
+#[derive(Debug, Clone)]
+pub struct CrackleLines {
+    numbers: RangeInclusive<u64>,
+}
+
+impl CrackleLines {
+    pub fn new(range: RangeInclusive<u64>) -> Self {
+        CrackleLines { numbers: range }
+    }
+
+    /// Replaces the contents of `line` with the next line, or returns `false`
+    /// and leaves it alone once the range is exhausted. `line` is grown to fit
+    /// the longest possible line up front, so after the first call this never
+    /// allocates.
+    pub fn next_into(&mut self, line: &mut String) -> bool {
+        let n = match self.numbers.next() {
+            Some(n) => n,
+            None => return false,
+        };
+        let mut rendered = [0; 21];
+        let len = render_line(n, &mut rendered);
+        line.clear();
+        line.reserve(rendered.len() - 1);
+        // Safe, as render_line only writes ASCII.
+        line.push_str(unsafe { std::str::from_utf8_unchecked(&rendered[..len - 1]) });
+        true
+    }
+}
+
+impl Iterator for CrackleLines {
+    type Item = String;
+
+    fn next(&mut self) -> Option<String> {
+        let mut line = String::new();
+        if self.next_into(&mut line) {
+            Some(line)
+        } else {
+            None
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.numbers.size_hint()
+    }
+}
+
+/// How long each phase of `crackle_pop_timed` took.
+#[cfg(feature = "phase-timings")]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Timings {
+    /// Working out the category of every number.
+    pub classify: std::time::Duration,
+    /// Turning the categories into bytes in a buffer.
+    pub encode: std::time::Duration,
+    /// Writing the buffer out to the sink.
+    pub flush: std::time::Duration,
+}
+
+/// Classic CracklePop to `out`, split into separately timed phases, so the
+/// cost of classifying, encoding and flushing (the `println!` overhead the
+/// main module docs go on about) can be measured rather than guessed at.
+/// Behind the `phase-timings` feature, to keep the `Instant` calls away from
+/// the benches.
+#[cfg(feature = "phase-timings")]
+pub fn crackle_pop_timed<W: Write>(out: &mut W) -> io::Result<Timings> {
+    use std::time::Instant;
+
+    let start = Instant::now();
+    let mut categories = [Category::Number; 100];
+    for (category, n) in categories.iter_mut().zip(1..) {
+        *category = Category::of(n);
+    }
+    let classify = start.elapsed();
+
+    let start = Instant::now();
+    let mut buf: ArrayBuffer<u8, { crate::exact_bytes(1, 100) }> = ArrayBuffer::new();
+    for (category, n) in categories.iter().zip(1..) {
+        match category.word() {
+            Some(word) => buf.push_buf_line(word),
+            None => buf.write_u8_as_utf8_with_newline(n),
+        }
+    }
+    let encode = start.elapsed();
+
+    let start = Instant::now();
+    buf.write_all_to(out)?;
+    let flush = start.elapsed();
+
+    Ok(Timings {
+        classify,
+        encode,
+        flush,
+    })
+}
+
+/// The configurable engine: any range, any divisors and words. Where
+/// `crackle_pop_into` hardcodes classic CracklePop for speed, this expresses
+/// the variants it can't (FizzBuzz, "Crackle Pop", a third rule...).
+#[derive(Debug, Clone)]
+pub struct Config<'a> {
+    pub range: RangeInclusive<u64>,
+    /// Divisors paired with the word they contribute. When several rules match
+    /// a number, their words are all written, in this order, even when one
+    /// divisor is a multiple of another or repeats: nothing is deduplicated.
+    /// A divisor of 0 never matches.
+    pub rules: Vec<(u64, &'a str)>,
+    /// Written between the words of a number matching more than one rule, but
+    /// never before the first word or after the last.
+    pub concat_sep: &'a [u8],
+    /// Written between lines, and after the last one too with
+    /// `trailing_newline`.
+    pub line_sep: &'a [u8],
+    pub trailing_newline: bool,
+    pub direction: Direction,
+    /// Writes the number of lines that follow as the very first line, for
+    /// length prefixed protocols.
+    pub prefix_with_count: bool,
+    /// The radix, from 2 to 36, that numbers are written in. Words are
+    /// unaffected, and so is the count from `prefix_with_count`.
+    pub radix: u32,
+}
+
+/// Which way the engine walks its range.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Ascending,
+    /// From the end of the range down to its start, like a countdown.
+    Descending,
+}
+
+/// Classic CracklePop over `1..=100`, trailing newline included.
+impl Default for Config<'_> {
+    fn default() -> Self {
+        Config {
+            range: 1..=100,
+            rules: vec![(3, "Crackle"), (5, "Pop")],
+            concat_sep: b"",
+            line_sep: b"\n",
+            trailing_newline: true,
+            direction: Direction::Ascending,
+            prefix_with_count: false,
+            radix: 10,
+        }
+    }
+}
+
+/// Builds a `Config` one option at a time, starting from classic CracklePop:
+///
+/// ```ignore
+/// let config = CracklePopBuilder::new()
+///     .range(1, 15)
+///     .rule(3, "Fizz")
+///     .rule(5, "Buzz")
+///     .separator(b", ")
+///     .trailing_newline(false)
+///     .build();
+/// config.run_into(&mut out)?;
+/// ```
+///
+/// The first call to `rule` replaces the classic Crackle and Pop rules, and
+/// later ones add to it.
+#[derive(Debug, Clone, Default)]
+pub struct CracklePopBuilder<'a> {
+    config: Config<'a>,
+    custom_rules: bool,
+}
+
+impl<'a> CracklePopBuilder<'a> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Runs over `start..=end`.
+    pub fn range(mut self, start: u64, end: u64) -> Self {
+        self.config.range = start..=end;
+        self
+    }
+
+    /// Writes `word` for multiples of `divisor`, see `Config::rules`.
+    pub fn rule(mut self, divisor: u64, word: &'a str) -> Self {
+        if !self.custom_rules {
+            self.config.rules.clear();
+            self.custom_rules = true;
+        }
+        self.config.rules.push((divisor, word));
+        self
+    }
+
+    /// Written between lines, a newline by default.
+    pub fn separator(mut self, line_sep: &'a [u8]) -> Self {
+        self.config.line_sep = line_sep;
+        self
+    }
+
+    /// Written between the words of a number matching several rules, nothing
+    /// by default.
+    pub fn concat_sep(mut self, concat_sep: &'a [u8]) -> Self {
+        self.config.concat_sep = concat_sep;
+        self
+    }
+
+    /// Whether the last line gets a separator too, as it does by default.
+    pub fn trailing_newline(mut self, trailing_newline: bool) -> Self {
+        self.config.trailing_newline = trailing_newline;
+        self
+    }
+
+    pub fn direction(mut self, direction: Direction) -> Self {
+        self.config.direction = direction;
+        self
+    }
+
+    pub fn prefix_with_count(mut self, prefix_with_count: bool) -> Self {
+        self.config.prefix_with_count = prefix_with_count;
+        self
+    }
+
+    /// The radix numbers are written in, checked once the config is run.
+    pub fn radix(mut self, radix: u32) -> Self {
+        self.config.radix = radix;
+        self
+    }
+
+    pub fn build(self) -> Config<'a> {
+        self.config
+    }
+}
+
+/// Classic CracklePop counting down from 100 to 1.
+pub fn crackle_pop_reverse<W: Write>(out: &mut W) -> io::Result<()> {
+    Config {
+        direction: Direction::Descending,
+        ..Config::default()
+    }
+    .run_into(out)
+}
+
+/// CracklePop over any range, however big, through an `ArrayBuffer<u8, N>`
+/// that's flushed to `sink` whenever the next line wouldn't fit, and once more
+/// at the end. So the sink sees a few large writes rather than one per line,
+/// while memory use stays fixed at `N`. Every line ends in a newline.
+///
+/// `N` must fit at least one line, which is 21 bytes for the longest u64; a
+/// smaller `N` fails to compile.
+pub fn crackle_pop_streaming<W: Write, const N: usize>(
+    range: RangeInclusive<u64>,
+    sink: &mut W,
+) -> io::Result<()> {
+    const { assert!(N >= 21, "the buffer must fit the longest line, 21 bytes") };
+
+    let mut buf: ArrayBuffer<u8, N> = ArrayBuffer::new();
+    let mut line = [0; 21];
+    for n in range {
+        let len = render_line(n, &mut line);
+        if !buf.has_room_for(len) {
+            buf.write_all_to(sink)?;
+        }
+        buf.push_buf(&line[..len]);
+    }
+    buf.write_all_to(sink)
+}
+
+/// CracklePop over `range` laid out as a grid of `cols` cells per row, each
+/// cell right-aligned to `cell_width`, with a newline after every row
+/// including a last partial one. A cell whose content is wider than
+/// `cell_width` is written in full, which shifts the rest of its row to the
+/// right.
+///
+/// The cells are assembled in an `ArrayBuffer` with `pad_to` doing the
+/// aligning, and flushed to `out` whenever it fills up, so padding wider than
+/// the buffer works too.
+///
+/// Returns an `InvalidInput` error, without writing anything, if `cols` is 0.
+pub fn crackle_pop_grid<W: Write>(
+    range: RangeInclusive<u64>,
+    cols: usize,
+    cell_width: usize,
+    out: &mut W,
+) -> io::Result<()> {
+    if cols == 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "a grid needs at least one column",
+        ));
+    }
+
+    let mut buf: ArrayBuffer<u8, 256> = ArrayBuffer::new();
+    let mut line = [0; 21];
+    let mut cells = 0;
+    for n in range {
+        // Everything but the newline is the cell's content.
+        let len = render_line(n, &mut line) - 1;
+        let mut padding = cell_width.saturating_sub(len);
+        while padding > 0 {
+            if buf.remaining() == 0 {
+                buf.write_all_to(out)?;
+            }
+            let spaces = padding.min(buf.remaining());
+            buf.pad_to(buf.len() + spaces, b' ');
+            padding -= spaces;
+        }
+
+        // The content and the row's newline.
+        if !buf.has_room_for(len + 1) {
+            buf.write_all_to(out)?;
+        }
+        buf.push_buf(&line[..len]);
+        cells += 1;
+        if cells % cols == 0 {
+            buf.push(b'\n');
+        }
+    }
+    // The last partial row. Its last cell left room for this.
+    if cells % cols != 0 {
+        buf.push(b'\n');
+    }
+    buf.write_all_to(out)
+}
+
+/// Resumable CracklePop over `next..=end`, for producing the output a page at
+/// a time across calls. Once `next > end` everything has been written.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CracklePopState {
+    pub next: u64,
+    pub end: u64,
+}
+
+impl CracklePopState {
+    /// Writes the lines for up to `max_lines` more numbers, each with its
+    /// newline, and returns how many it wrote: 0 once finished. Every line is
+    /// handed to `out` in a single `write_all`, so a page never ends partway
+    /// into a line, and `next` only moves past lines that were written
+    /// successfully.
+    pub fn fill_next<W: Write>(&mut self, out: &mut W, max_lines: usize) -> io::Result<usize> {
+        let mut line = [0; 21];
+        let mut written = 0;
+        while written < max_lines && self.next <= self.end {
+            let len = render_line(self.next, &mut line);
+            out.write_all(&line[..len])?;
+            written += 1;
+            match self.next.checked_add(1) {
+                Some(next) => self.next = next,
+                // `end` was u64::MAX, which `next` can't get past, so mark the
+                // state finished by pulling `end` below it instead.
+                None => self.end = self.next - 1,
+            }
+        }
+        Ok(written)
+    }
+}
+
+/// CracklePop over `range` as binary records, for protocols that don't want
+/// text: a 1 byte tag, the `Category` discriminant (0 for a number, 1 Crackle,
+/// 2 Pop, 3 CracklePop), and for numbers the number as a little-endian u32
+/// right after it. Words are just the tag.
+///
+/// Returns an `InvalidInput` error for a number that doesn't fit in a u32,
+/// once everything before it has been written.
+pub fn crackle_pop_binary<W: Write>(range: RangeInclusive<u64>, out: &mut W) -> io::Result<()> {
+    for (n, category) in classify(range) {
+        if category != Category::Number {
+            out.write_all(&[category as u8])?;
+            continue;
+        }
+        let n = u32::try_from(n).map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{} doesn't fit in a binary record", n),
+            )
+        })?;
+        let [a, b, c, d] = n.to_le_bytes();
+        out.write_all(&[category as u8, a, b, c, d])?;
+    }
+    Ok(())
+}
+
+/// Classic CracklePop over `range` into the file at `path`, created or
+/// truncated, by writing straight into a memory mapping of it rather than
+/// through `write` calls. The file is first sized to `crackle_pop_max_bytes`,
+/// since a mapping can't grow, and truncated back to what was actually
+/// written afterwards.
+#[cfg(feature = "memmap2")]
+pub fn crackle_pop_to_mmap<P: AsRef<std::path::Path>>(
+    range: RangeInclusive<u64>,
+    path: P,
+) -> io::Result<()> {
+    use std::fs::OpenOptions;
+
+    let file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(path)?;
+    let max_bytes =
+        crate::crackle_pop_max_bytes(*range.start(), *range.end(), &[(3, "Crackle"), (5, "Pop")]);
+    // There's no mapping an empty file, and nothing to write anyway.
+    if max_bytes == 0 {
+        return Ok(());
+    }
+    file.set_len(max_bytes as u64)?;
+
+    // Safe as long as nothing else truncates or writes to the file while it's
+    // mapped, which we just created the file to make unlikely.
+    let mut map = unsafe { memmap2::MmapMut::map_mut(&file)? };
+    let mut rest = &mut map[..];
+    crackle_pop_into(range, &mut rest, true)?;
+    let written = max_bytes - rest.len();
+    map.flush()?;
+    drop(map);
+    file.set_len(written as u64)
+}
+
+/// SGR escape sequences for `crackle_pop_colored`.
+const CRACKLE_COLOR: &[u8] = b"\x1b[31m";
+const POP_COLOR: &[u8] = b"\x1b[34m";
+const NUMBER_COLOR: &[u8] = b"\x1b[32m";
+const RESET: &[u8] = b"\x1b[0m";
+
+/// Classic CracklePop for a terminal: Crackle in red, Pop in blue and numbers
+/// in green. Plain output if the `NO_COLOR` environment variable is set to
+/// anything non-empty, see https://no-color.org.
+pub fn crackle_pop_colored<W: Write>(out: &mut W) -> io::Result<()> {
+    let no_color = std::env::var_os("NO_COLOR").is_some_and(|v| !v.is_empty());
+    write_colored(1..=100, out, !no_color)
+}
+
+/// `crackle_pop_into` with every token wrapped in its color when `color` is
+/// set, and exactly `crackle_pop_into` otherwise.
+fn write_colored<W: Write>(range: RangeInclusive<u64>, out: &mut W, color: bool) -> io::Result<()> {
+    if !color {
+        return crackle_pop_into(range, out, true);
+    }
+    for n in range {
+        let category = Category::of(n);
+        if category == Category::Number {
+            out.write_all(NUMBER_COLOR)?;
+            crate::write_u64_as_utf8(n, out)?;
+            out.write_all(RESET)?;
+        }
+        // CracklePop is colored as its two halves.
+        if matches!(category, Category::Crackle | Category::CracklePop) {
+            out.write_all(CRACKLE_COLOR)?;
+            Category::Crackle.render(n, out)?;
+            out.write_all(RESET)?;
+        }
+        if matches!(category, Category::Pop | Category::CracklePop) {
+            out.write_all(POP_COLOR)?;
+            Category::Pop.render(n, out)?;
+            out.write_all(RESET)?;
+        }
+        out.write_all(b"\n")?;
+    }
+    Ok(())
+}
+
+impl Config<'_> {
+    /// Writes the configured CracklePop to `out`. An invalid `radix` is an
+    /// `InvalidInput` error before anything is written.
+    pub fn run_into<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        crate::check_radix(self.radix)?;
+        match self.direction {
+            Direction::Ascending => self.run_numbers_into(self.range.clone(), out),
+            Direction::Descending => self.run_numbers_into(self.range.clone().rev(), out),
+        }
+    }
+
+    fn run_numbers_into<I, W>(&self, numbers: I, out: &mut W) -> io::Result<()>
+    where
+        I: Iterator<Item = u64>,
+        W: Write,
+    {
+        let mut first = true;
+        if self.prefix_with_count {
+            // One line per number. Worked out in u128 since the whole u64
+            // range has one more line than fits in a u64.
+            let count = if self.range.is_empty() {
+                0
+            } else {
+                u128::from(self.range.end() - self.range.start()) + 1
+            };
+            write!(out, "{}", count)?;
+            first = false;
+        }
+        for n in numbers {
+            if !first {
+                out.write_all(self.line_sep)?;
+            }
+            first = false;
+            self.write_line(n, out)?;
+        }
+
+        if self.trailing_newline && !first {
+            out.write_all(self.line_sep)?;
+        }
+        Ok(())
+    }
+
+    /// Writes the line for `n`, without its separator.
+    fn write_line<W: Write>(&self, n: u64, out: &mut W) -> io::Result<()> {
+        let mut matched = false;
+        for &(divisor, word) in &self.rules {
+            if divisor == 0 || !n.is_multiple_of(divisor) {
+                continue;
+            }
+            if matched {
+                out.write_all(self.concat_sep)?;
+            }
+            matched = true;
+            out.write_all(word.as_bytes())?;
+        }
+
+        if !matched {
+            if self.radix == 10 {
+                crate::write_u64_as_utf8(n, out)?;
+            } else {
+                crate::write_uint_radix(n, self.radix, out)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ARRAY_BUFFER_SIZE;
+
+    fn run(range: RangeInclusive<u64>, trailing_newline: bool) -> String {
+        let mut out = Vec::new();
+        crackle_pop_into(range, &mut out, trailing_newline).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    fn run_config(config: &Config<'_>) -> String {
+        let mut out = Vec::new();
+        config.run_into(&mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn counts_for_one_to_a_hundred() {
+        assert_eq!(count_crackle(1..=100), 27);
+        assert_eq!(count_pop(1..=100), 14);
+        assert_eq!(count_cracklepop(1..=100), 6);
+        assert_eq!(count_numbers(1..=100), 53);
+        assert_eq!(count_matching(1..=100, |n| n % 2 == 0), 50);
+
+        let out = run(1..=100, true);
+        assert_eq!(out.lines().filter(|&l| l == "Crackle").count(), 27);
+        #[allow(clippy::reversed_empty_ranges)]
+        let empty = 5..=4;
+        assert_eq!(count_numbers(empty), 0);
+    }
+
+    #[test]
+    fn crackle_pop_on_ranges() {
+        let mut v = Vec::new();
+        (1..=15).crackle_pop_into(&mut v).unwrap();
+        assert_eq!(
+            String::from_utf8(v).unwrap(),
+            "1\n2\nCrackle\n4\nPop\nCrackle\n7\n8\nCrackle\nPop\n11\nCrackle\n13\n14\nCracklePop\n"
+        );
+
+        let mut v = Vec::new();
+        (1..16).crackle_pop_into(&mut v).unwrap();
+        assert_eq!(String::from_utf8(v).unwrap(), run(1..=15, true));
+
+        let mut v = Vec::new();
+        (0..0).crackle_pop_into(&mut v).unwrap();
+        assert!(v.is_empty());
+    }
+
+    #[test]
+    fn writes_into_a_borrowed_slice() {
+        let expected = run(1..=100, true);
+
+        let mut slice = [0u8; 1024];
+        let mut cursor = io::Cursor::new(&mut slice[..]);
+        crackle_pop_into(1..=100, &mut cursor, true).unwrap();
+        let len = cursor.position() as usize;
+        assert_eq!(&slice[..len], expected.as_bytes());
+
+        let mut slice = [0u8; 10];
+        let err =
+            crackle_pop_into(1..=100, &mut io::Cursor::new(&mut slice[..]), true).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::WriteZero);
+        assert_eq!(&slice, &expected.as_bytes()[..10]);
+    }
+
+    #[cfg(feature = "phase-timings")]
+    #[test]
+    fn timed_writes_classic_output() {
+        let mut out = Vec::new();
+        let timings = crackle_pop_timed(&mut out).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), run(1..=100, true));
+        assert!(timings.classify + timings.encode + timings.flush > std::time::Duration::ZERO);
+    }
+
+    #[test]
+    fn classify_pairs_numbers_with_categories() {
+        use Category::*;
+        let categories: Vec<_> = classify(1..=15).map(|(_, category)| category).collect();
+        assert_eq!(
+            categories,
+            [
+                Number, Number, Crackle, Number, Pop, Crackle, Number, Number, Crackle, Pop,
+                Number, Crackle, Number, Number, CracklePop
+            ]
+        );
+        assert!(classify(1..=15).map(|(n, _)| n).eq(1..=15));
+    }
+
+    #[test]
+    fn binary_records_round_trip() {
+        let mut out = Vec::new();
+        crackle_pop_binary(1..=100, &mut out).unwrap();
+        assert_eq!(&out[..7], &[0, 1, 0, 0, 0, 0, 2]);
+
+        let mut records = &out[..];
+        let mut lines = String::new();
+        while let Some((&tag, rest)) = records.split_first() {
+            records = rest;
+            match tag {
+                0 => {
+                    let (n, rest) = records.split_at(4);
+                    records = rest;
+                    lines += &u32::from_le_bytes([n[0], n[1], n[2], n[3]]).to_string();
+                }
+                1 => lines += "Crackle",
+                2 => lines += "Pop",
+                3 => lines += "CracklePop",
+                _ => panic!("unknown tag {}", tag),
+            }
+            lines.push('\n');
+        }
+        assert_eq!(lines, run(1..=100, true));
+
+        let mut out = Vec::new();
+        let max = u64::from(u32::MAX);
+        let err = crackle_pop_binary(max - 1..=max + 1, &mut out).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        // u32::MAX happens to be a multiple of 15.
+        assert_eq!(out, [0, 0xfe, 0xff, 0xff, 0xff, 3]);
+    }
+
+    #[test]
+    fn array_buffer_extends_like_a_vec() {
+        let mut vec = Vec::new();
+        let mut buf: ArrayBuffer<u8, 64> = ArrayBuffer::new();
+        for word in [&b"Crackle"[..], b"\n", b"Pop", b"\n"] {
+            vec.extend_from_slice(word);
+            buf.extend_from_slice(word);
+        }
+        assert_eq!(buf.as_slice(), &vec[..]);
+
+        let mut units: ArrayBuffer<u16, 4> = ArrayBuffer::new();
+        units.extend_from_slice(&[1, 2, 3]);
+        assert_eq!(units.as_slice(), &[1, 2, 3]);
+    }
+
+    #[test]
+    fn crackle_lines_render_whole_lines() {
+        let lines: Vec<String> = CrackleLines::new(1..=100).collect();
+        assert_eq!(lines.len(), 100);
+        assert_eq!(lines.join("\n") + "\n", run(1..=100, true));
+
+        let mut lines = CrackleLines::new(1..=100);
+        let mut line = String::new();
+        assert!(lines.next_into(&mut line));
+        assert_eq!(line, "1");
+        let (ptr, capacity) = (line.as_ptr(), line.capacity());
+        for expected in CrackleLines::new(2..=100) {
+            assert!(lines.next_into(&mut line));
+            assert_eq!(line, expected);
+            assert_eq!((line.as_ptr(), line.capacity()), (ptr, capacity));
+        }
+        assert!(!lines.next_into(&mut line));
+        assert_eq!(line, "Pop");
+
+        let mut lines = CrackleLines::new(u64::MAX - 1..=u64::MAX);
+        assert_eq!(lines.next().unwrap(), "18446744073709551614");
+    }
+
+    #[test]
+    fn zero_is_cracklepop() {
+        assert_eq!(Category::of(0), Category::CracklePop);
+        assert_eq!(run(0..=3, true), "CracklePop\n1\n2\nCrackle\n");
+        assert_eq!(run(0..=0, false), "CracklePop");
+        assert_eq!(crate::exact_bytes(0, 15), run(0..=15, true).len());
+        assert_eq!(count_cracklepop(0..=15), 2);
+    }
+
+    #[cfg(feature = "memmap2")]
+    #[test]
+    fn mmap_output_is_truncated_to_the_exact_length() {
+        let path = std::env::temp_dir().join(format!("crackle_pop_mmap_{}", std::process::id()));
+        for range in [1..=100, 999_990..=1_000_010] {
+            crackle_pop_to_mmap(range.clone(), &path).unwrap();
+            let written = std::fs::read(&path).unwrap();
+            assert_eq!(String::from_utf8(written).unwrap(), run(range, true));
+        }
+
+        #[allow(clippy::reversed_empty_ranges)]
+        let empty = 5..=4;
+        crackle_pop_to_mmap(empty, &path).unwrap();
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), 0);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn categories_render_their_bytes() {
+        fn render(category: Category, n: u64) -> String {
+            let mut out = Vec::new();
+            category.render(n, &mut out).unwrap();
+            String::from_utf8(out).unwrap()
+        }
+
+        assert_eq!(render(Category::Crackle, 3), "Crackle");
+        assert_eq!(render(Category::Pop, 5), "Pop");
+        assert_eq!(render(Category::CracklePop, 15), "CracklePop");
+        assert_eq!(render(Category::Number, 7), "7");
+        assert_eq!(render(Category::Number, 1_000_001), "1000001");
+        assert_eq!(
+            render(Category::of(u64::MAX - 1), u64::MAX - 1),
+            "18446744073709551614"
+        );
+        assert_eq!(Category::Number.word(), None);
+        assert_eq!(Category::CracklePop.word(), Some(&b"CracklePop"[..]));
+    }
+
+    #[test]
+    fn default_config_is_classic_crackle_pop() {
+        assert_eq!(run_config(&Config::default()), run(1..=100, true));
+    }
+
+    #[test]
+    fn concat_sep_only_goes_between_words() {
+        let config = Config {
+            range: 14..=16,
+            concat_sep: b"-",
+            ..Config::default()
+        };
+        assert_eq!(run_config(&config), "14\nCrackle-Pop\n16\n");
+
+        let config = Config {
+            range: 1..=15,
+            rules: vec![(3, "Fizz"), (5, "Buzz")],
+            concat_sep: b" ",
+            trailing_newline: false,
+            ..Config::default()
+        };
+        let out = run_config(&config);
+        assert!(out.starts_with("1\n2\nFizz\n4\nBuzz\nFizz\n"));
+        assert!(out.ends_with("\nFizz Buzz"));
+    }
+
+    #[test]
+    fn overlapping_rules_each_contribute_in_order() {
+        let config = Config {
+            range: 14..=16,
+            rules: vec![(3, "Crackle"), (5, "Pop"), (15, "Boom")],
+            ..Config::default()
+        };
+        assert_eq!(run_config(&config), "14\nCracklePopBoom\n16\n");
+
+        let config = Config {
+            range: 30..=30,
+            rules: vec![(15, "Boom"), (5, "Pop"), (3, "Crackle"), (2, "Fizz")],
+            concat_sep: b"+",
+            ..Config::default()
+        };
+        assert_eq!(run_config(&config), "Boom+Pop+Crackle+Fizz\n");
+
+        // The same divisor twice contributes twice, and 0 never matches.
+        let config = Config {
+            range: 9..=10,
+            rules: vec![(3, "Crackle"), (0, "Never"), (3, "Crackle")],
+            ..Config::default()
+        };
+        assert_eq!(run_config(&config), "CrackleCrackle\n10\n");
+    }
+
+    #[test]
+    fn descending_counts_down() {
+        let mut out = Vec::new();
+        crackle_pop_reverse(&mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.starts_with("Pop\nCrackle\n98\n97\nCrackle\n"));
+        assert!(out.ends_with("\nPop\n4\nCrackle\n2\n1\n"));
+
+        let config = Config {
+            range: 1..=5,
+            trailing_newline: false,
+            direction: Direction::Descending,
+            ..Config::default()
+        };
+        assert_eq!(run_config(&config), "Pop\n4\nCrackle\n2\n1");
+    }
+
+    #[test]
+    fn prefix_with_count_matches_what_follows() {
+        for &trailing_newline in &[true, false] {
+            let config = Config {
+                trailing_newline,
+                prefix_with_count: true,
+                ..Config::default()
+            };
+            let out = run_config(&config);
+            let (count, rest) = out.split_once('\n').unwrap();
+            assert_eq!(count, "100");
+            assert_eq!(rest.lines().count(), 100);
+            assert_eq!(rest, run(1..=100, trailing_newline));
+        }
+
+        #[allow(clippy::reversed_empty_ranges)]
+        let config = Config {
+            range: 5..=4,
+            prefix_with_count: true,
+            ..Config::default()
+        };
+        assert_eq!(run_config(&config), "0\n");
+
+        let config = Config {
+            range: 1..=3,
+            direction: Direction::Descending,
+            prefix_with_count: true,
+            trailing_newline: false,
+            ..Config::default()
+        };
+        assert_eq!(run_config(&config), "3\nCrackle\n2\n1");
+    }
+
+    #[test]
+    fn radix_only_changes_numbers() {
+        let config = Config {
+            range: 1..=16,
+            radix: 2,
+            trailing_newline: false,
+            ..Config::default()
+        };
+        let out = run_config(&config);
+        assert!(out.starts_with("1\n10\nCrackle\n100\nPop\n"));
+        assert!(out.ends_with("\n1110\nCracklePop\n10000"));
+
+        let config = Config {
+            radix: 37,
+            ..Config::default()
+        };
+        let mut out = Vec::new();
+        let err = config.run_into(&mut out).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert!(out.is_empty());
+    }
+
+    #[test]
+    fn builder_defaults_to_classic_crackle_pop() {
+        let config = CracklePopBuilder::new().build();
+        assert_eq!(run_config(&config), run(1..=100, true));
+
+        let config = CracklePopBuilder::new()
+            .range(1, 100)
+            .rule(3, "Crackle")
+            .rule(5, "Pop")
+            .separator(b"\n")
+            .trailing_newline(false)
+            .build();
+        assert_eq!(run_config(&config), run(1..=100, false));
+    }
+
+    #[test]
+    fn builder_sets_every_option() {
+        let config = CracklePopBuilder::new()
+            .range(13, 16)
+            .rule(3, "Fizz")
+            .rule(5, "Buzz")
+            .concat_sep(b" ")
+            .separator(b", ")
+            .direction(Direction::Descending)
+            .prefix_with_count(true)
+            .radix(16)
+            .build();
+        assert_eq!(run_config(&config), "4, 10, Fizz Buzz, e, d, ");
+    }
+
+    #[test]
+    fn trailing_newline_is_optional() {
+        assert_eq!(run(1..=5, false), "1\n2\nCrackle\n4\nPop");
+        assert_eq!(run(1..=5, true), "1\n2\nCrackle\n4\nPop\n");
+        assert_eq!(run(14..=16, true), "14\nCracklePop\n16\n");
+    }
+
+    #[test]
+    fn empty_range_writes_nothing() {
+        #[allow(clippy::reversed_empty_ranges)]
+        let range = 5..=4;
+        assert_eq!(run(range.clone(), true), "");
+        assert_eq!(run(range, false), "");
+    }
+
+    #[test]
+    fn iterator_yields_reference_output() {
+        let expected = run(1..=100, true);
+        let bytes: Vec<u8> = CracklePopIterator::new(1..=100).collect();
+        assert_eq!(String::from_utf8(bytes).unwrap(), expected);
+
+        let mut read = Vec::new();
+        io::copy(&mut CracklePopIterator::new(1..=100), &mut read).unwrap();
+        assert_eq!(String::from_utf8(read).unwrap(), expected);
+
+        let iter = CracklePopIterator::new(1..=100);
+        assert_eq!(iter.size_hint(), (expected.len(), Some(expected.len())));
+        let middle: Vec<u8> = iter.skip(4).take(8).collect();
+        assert_eq!(middle, b"Crackle\n");
+
+        #[allow(clippy::reversed_empty_ranges)]
+        let empty = CracklePopIterator::new(5..=4);
+        assert_eq!(empty.count(), 0);
+    }
+
+    #[test]
+    fn iterator_over_a_huge_range() {
+        let iter = CracklePopIterator::new(1..=u64::MAX);
+        assert_eq!(iter.size_hint(), (usize::MAX, None));
+        let first: Vec<u8> = Iterator::take(iter, 8).collect();
+        assert_eq!(first, b"1\n2\nCrac");
+
+        // Partway into a line, that line's rest is counted too.
+        let mut iter = CracklePopIterator::new(u64::MAX - 1..=u64::MAX);
+        iter.next();
+        let rest = crate::exact_bytes(u64::MAX - 1, u64::MAX) - 1;
+        assert_eq!(iter.size_hint(), (rest, Some(rest)));
+        assert_eq!(iter.count(), rest);
+    }
+
+    #[test]
+    fn iterator_reads_into_small_buffers() {
+        let mut iter = CracklePopIterator::new(14..=16);
+        let mut buf = [0; 4];
+        let mut out = Vec::new();
+        loop {
+            let n = iter.read(&mut buf).unwrap();
+            if n == 0 {
+                break;
+            }
+            out.extend_from_slice(&buf[..n]);
+        }
+        assert_eq!(out, b"14\nCracklePop\n16\n");
+    }
+
+    #[test]
+    fn colored_wraps_every_token() {
+        let mut out = Vec::new();
+        write_colored(14..=16, &mut out, true).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "\x1b[32m14\x1b[0m\n\
+             \x1b[31mCrackle\x1b[0m\x1b[34mPop\x1b[0m\n\
+             \x1b[32m16\x1b[0m\n"
+        );
+
+        let mut out = Vec::new();
+        write_colored(1..=100, &mut out, false).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), run(1..=100, true));
+    }
+
+    #[test]
+    fn state_resumes_across_pages() {
+        let mut state = CracklePopState { next: 1, end: 100 };
+        let mut out = Vec::new();
+        let mut pages = 0;
+        loop {
+            let page_start = out.len();
+            let lines = state.fill_next(&mut out, 7).unwrap();
+            if lines == 0 {
+                break;
+            }
+            pages += 1;
+            // Pages only ever end on a line boundary.
+            assert!(out.ends_with(b"\n"));
+            assert_eq!(
+                out[page_start..].iter().filter(|&&b| b == b'\n').count(),
+                lines
+            );
+        }
+        assert_eq!(pages, 15);
+        assert_eq!(String::from_utf8(out).unwrap(), run(1..=100, true));
+        assert_eq!(state.fill_next(&mut Vec::new(), 7).unwrap(), 0);
+
+        let mut state = CracklePopState {
+            next: u64::MAX - 1,
+            end: u64::MAX,
+        };
+        let mut out = Vec::new();
+        assert_eq!(state.fill_next(&mut out, 5).unwrap(), 2);
+        assert!(state.next > state.end);
+        assert_eq!(state.fill_next(&mut out, 5).unwrap(), 0);
+        assert_eq!(out, b"18446744073709551614\nCracklePop\n");
+    }
+
+    #[test]
+    fn streaming_flushes_whenever_the_buffer_fills() {
+        /// Records the size of every write.
+        #[derive(Default)]
+        struct Writes(Vec<u8>, Vec<usize>);
+        impl Write for Writes {
+            fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+                self.0.extend_from_slice(buf);
+                self.1.push(buf.len());
+                Ok(buf.len())
+            }
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let mut sink = Writes::default();
+        crackle_pop_streaming::<_, 32>(1..=10_000, &mut sink).unwrap();
+        assert_eq!(String::from_utf8(sink.0).unwrap(), run(1..=10_000, true));
+        assert!(sink.1.len() > 1000);
+        assert!(sink.1.iter().all(|&len| len <= 32));
+
+        let mut sink = Writes::default();
+        crackle_pop_streaming::<_, 4096>(1..=100, &mut sink).unwrap();
+        assert_eq!(sink.1, vec![crate::exact_bytes(1, 100)]);
+
+        let mut out = Vec::new();
+        crackle_pop_streaming::<_, 21>(u64::MAX - 1..=u64::MAX, &mut out).unwrap();
+        assert_eq!(out, b"18446744073709551614\nCracklePop\n");
+    }
+
+    /// Pins the exact output, trailing newline included, against a committed
+    /// golden file rather than against another implementation.
+    #[test]
+    fn matches_golden_file() {
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/crackle_pop_1_100.txt");
+        let golden = std::fs::read(path).unwrap();
+
+        let mut buf: ArrayBuffer<u8, ARRAY_BUFFER_SIZE> = ArrayBuffer::new();
+        crackle_pop_into(1..=100, &mut buf, true).unwrap();
+        assert!(buf.eq_bytes(&golden));
+    }
+
+    #[test]
+    fn grid_right_aligns_cells() {
+        let mut out = Vec::new();
+        crackle_pop_grid(1..=100, 8, 11, &mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        let rows: Vec<&str> = out.lines().collect();
+        assert_eq!(rows.len(), 13);
+        assert_eq!(
+            rows[1],
+            "    Crackle        Pop         11    Crackle         13         14 CracklePop         16"
+        );
+        // The last row only has the 4 numbers left over.
+        assert_eq!(rows[12], "         97         98    Crackle        Pop");
+        assert!(out.ends_with("Pop\n"));
+
+        // Too narrow cells just run together.
+        let mut out = Vec::new();
+        crackle_pop_grid(1..=100, 5, 2, &mut out).unwrap();
+        assert!(out.starts_with(b" 1 2Crackle 4Pop\nCrackle 7 8CracklePop\n"));
+
+        // Any range, and padding far wider than the buffer it's built in.
+        let mut out = Vec::new();
+        crackle_pop_grid(14..=16, 2, 300, &mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        let rows: Vec<&str> = out.lines().collect();
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0], format!("{:>300}{:>300}", "14", "CracklePop"));
+        assert_eq!(rows[1], format!("{:>300}", "16"));
+
+        #[allow(clippy::reversed_empty_ranges)]
+        let empty = 5..=4;
+        let mut out = Vec::new();
+        crackle_pop_grid(empty, 3, 4, &mut out).unwrap();
+        assert!(out.is_empty());
+
+        let err = crackle_pop_grid(1..=100, 0, 4, &mut Vec::new()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn all_buffer_variants_produce_identical_output() {
+        let expected = run(1..=100, true);
+
+        let mut vec = Vec::new();
+        crate::crackle_pop_ext_vec_minimal_vars(&mut vec);
+        assert_eq!(String::from_utf8_lossy(&vec), expected);
+
+        vec.clear();
+        crate::crackle_pop_fastest_vec(&mut vec);
+        assert_eq!(String::from_utf8_lossy(&vec), expected);
+
+        let mut buf: ArrayBuffer<u8, ARRAY_BUFFER_SIZE> = ArrayBuffer::new();
+        crate::crackle_pop_fastest_arraybuf(&mut buf);
+        assert_eq!(String::from_utf8_lossy(&buf[..buf.pos]), expected);
+
+        let mut buf: ArrayBuffer<u8, ARRAY_BUFFER_SIZE> = ArrayBuffer::new();
+        crate::crackle_pop_ext_arraybuf_minimal_vars(&mut buf);
+        assert_eq!(String::from_utf8_lossy(&buf[..buf.pos]), expected);
+
+        // The trimmed variants match once the trailing newline is dropped.
+        assert_eq!(run(1..=100, false), expected.trim());
+    }
+}
@@ -0,0 +1,82 @@
+//! The one error type for everything fallible in the crate, so that callers
+//! have a single thing to match on instead of a mix of panics and raw
+//! `io::Error`s.
+
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::str::Utf8Error;
+
+#[derive(Debug)]
+pub enum CrackleError {
+    /// A write needed more room than the buffer had left.
+    Capacity {
+        needed: usize,
+        available: usize,
+    },
+    Io(io::Error),
+    InvalidUtf8(Utf8Error),
+}
+
+impl fmt::Display for CrackleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CrackleError::Capacity { needed, available } => write!(
+                f,
+                "buffer too small: needed {} bytes but only {} were available",
+                needed, available
+            ),
+            CrackleError::Io(err) => write!(f, "io error: {}", err),
+            CrackleError::InvalidUtf8(err) => write!(f, "invalid utf8: {}", err),
+        }
+    }
+}
+
+impl Error for CrackleError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            CrackleError::Capacity { .. } => None,
+            CrackleError::Io(err) => Some(err),
+            CrackleError::InvalidUtf8(err) => Some(err),
+        }
+    }
+}
+
+impl From<io::Error> for CrackleError {
+    fn from(err: io::Error) -> Self {
+        CrackleError::Io(err)
+    }
+}
+
+impl From<Utf8Error> for CrackleError {
+    fn from(err: Utf8Error) -> Self {
+        CrackleError::InvalidUtf8(err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn display_and_source() {
+        let err = CrackleError::Capacity {
+            needed: 8,
+            available: 3,
+        };
+        assert_eq!(
+            err.to_string(),
+            "buffer too small: needed 8 bytes but only 3 were available"
+        );
+        assert!(err.source().is_none());
+
+        let err: CrackleError = io::Error::other("oh no").into();
+        assert_eq!(err.to_string(), "io error: oh no");
+        assert!(err.source().is_some());
+
+        #[allow(invalid_from_utf8)]
+        let utf8_err = std::str::from_utf8(&[0xff]).unwrap_err();
+        let err: CrackleError = utf8_err.into();
+        assert!(matches!(err, CrackleError::InvalidUtf8(_)));
+    }
+}
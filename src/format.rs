@@ -0,0 +1,158 @@
+//! The `--format` option, for feeding CracklePop into scripts and spreadsheets
+//! rather than reading it off a terminal.
+//!
+//! Run with `crackle_pop --format {lines,json,csv}`.
+
+use std::io::{self, Write};
+use std::ops::RangeInclusive;
+use std::process;
+
+use crate::engine::{self, Category};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// One line per number, as written by `engine::crackle_pop_into`.
+    Lines,
+    /// A single array mixing numbers and strings: `[1,2,"Crackle",...]`.
+    Json,
+    /// An `index,value` header, then one row per number with the words
+    /// quoted: `3,"Crackle"`.
+    Csv,
+}
+
+impl Format {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "lines" => Some(Format::Lines),
+            "json" => Some(Format::Json),
+            "csv" => Some(Format::Csv),
+            _ => None,
+        }
+    }
+
+    /// Renders CracklePop over `range` in this format. Every format ends with
+    /// a newline.
+    pub fn write<W: Write>(self, range: RangeInclusive<u64>, out: &mut W) -> io::Result<()> {
+        match self {
+            Format::Lines => engine::crackle_pop_into(range, out, true),
+            Format::Json => write_json(range, out),
+            Format::Csv => write_csv(range, out),
+        }
+    }
+}
+
+/// `n` rendered as a JSON or CSV value: numbers bare, words quoted. None of
+/// the words need escaping, so they're written as is.
+fn write_value<W: Write>(n: u64, out: &mut W) -> io::Result<()> {
+    let category = Category::of(n);
+    if category == Category::Number {
+        return category.render(n, out);
+    }
+    out.write_all(b"\"")?;
+    category.render(n, out)?;
+    out.write_all(b"\"")
+}
+
+fn write_json<W: Write>(range: RangeInclusive<u64>, out: &mut W) -> io::Result<()> {
+    out.write_all(b"[")?;
+    let mut first = true;
+    for n in range {
+        if !first {
+            out.write_all(b",")?;
+        }
+        first = false;
+        write_value(n, out)?;
+    }
+    out.write_all(b"]\n")
+}
+
+fn write_csv<W: Write>(range: RangeInclusive<u64>, out: &mut W) -> io::Result<()> {
+    out.write_all(b"index,value\n")?;
+    for n in range {
+        crate::write_u64_as_utf8(n, out)?;
+        out.write_all(b",")?;
+        write_value(n, out)?;
+        out.write_all(b"\n")?;
+    }
+    Ok(())
+}
+
+pub fn main(args: &[String]) {
+    let format = match args {
+        [name] => Format::parse(name),
+        _ => None,
+    };
+    let format = match format {
+        Some(format) => format,
+        None => {
+            eprintln!("usage: crackle_pop --format {{lines,json,csv}}");
+            process::exit(2);
+        }
+    };
+
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    if let Err(err) = format.write(1..=100, &mut out).and_then(|_| out.flush()) {
+        eprintln!("{}", err);
+        process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn render(format: Format, range: RangeInclusive<u64>) -> String {
+        let mut out = Vec::new();
+        format.write(range, &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn parses_format_names() {
+        assert_eq!(Format::parse("lines"), Some(Format::Lines));
+        assert_eq!(Format::parse("json"), Some(Format::Json));
+        assert_eq!(Format::parse("csv"), Some(Format::Csv));
+        assert_eq!(Format::parse("JSON"), None);
+    }
+
+    #[test]
+    fn lines_golden_output() {
+        assert_eq!(render(Format::Lines, 14..=16), "14\nCracklePop\n16\n");
+    }
+
+    #[test]
+    fn json_golden_output() {
+        assert_eq!(
+            render(Format::Json, 1..=6),
+            "[1,2,\"Crackle\",4,\"Pop\",\"Crackle\"]\n"
+        );
+        #[allow(clippy::reversed_empty_ranges)]
+        let empty = 5..=4;
+        assert_eq!(render(Format::Json, empty), "[]\n");
+    }
+
+    #[test]
+    fn csv_golden_output() {
+        assert_eq!(
+            render(Format::Csv, 14..=16),
+            "index,value\n14,14\n15,\"CracklePop\"\n16,16\n"
+        );
+    }
+
+    #[test]
+    fn csv_round_trips_to_lines() {
+        let csv = render(Format::Csv, 1..=100);
+        let mut rows = csv.lines();
+        assert_eq!(rows.next(), Some("index,value"));
+
+        let mut lines = String::new();
+        for (row, n) in rows.zip(1..) {
+            let (index, value) = row.split_once(',').unwrap();
+            assert_eq!(index.parse::<u64>().unwrap(), n);
+            lines.push_str(value.trim_matches('"'));
+            lines.push('\n');
+        }
+        assert_eq!(lines, render(Format::Lines, 1..=100));
+    }
+}
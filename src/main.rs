@@ -20,17 +20,38 @@
 //! Again with the vec a bit slower, but barely. Our println! overhead is quite significant, accounting
 //! for roughly 1/4 - 1/3 of the total time.
 #![warn(missing_debug_implementations, rust_2018_idioms)]
-#![feature(test, array_value_iter)]
+#![cfg_attr(test, feature(test))]
 
+mod buffered;
+pub mod dyn_buffer;
+pub mod engine;
+pub mod error;
+mod format;
 mod rc_sub;
+#[cfg(feature = "simd")]
+pub mod simd;
+pub mod static_buffer;
+mod timing;
+mod trace;
 
-use std::array::IntoIter;
+use std::any::Any;
+use std::fmt;
 use std::io::{self, prelude::*};
-use std::ops::Deref;
+use std::ops::{Deref, DerefMut, Index, Range, RangeFrom, RangeFull, RangeTo};
+use std::ptr;
 use std::str;
+use std::time::Instant;
+
+use error::CrackleError;
 
 pub fn main() {
-    rc_sub::main()
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match args.first().map(String::as_str) {
+        Some("bench") => timing::main(&args[1..]),
+        Some("--format") => format::main(&args[1..]),
+        Some("--buffer-size") | Some("--end") => buffered::main(&args),
+        _ => rc_sub::main(),
+    }
 }
 
 /// 512 bytes, just enough for this problem. Can also test benchmarks with
@@ -55,11 +76,160 @@ pub fn main() {
 /// performance (yikes). From this observation we should be more keen on
 /// re-using buffers, and also potentially invest in creating a dynamically
 /// sized one (but of course, stack rather than heap allocated).
+///
+/// The `buffer_size_*` benches reproduce these numbers, so run those after
+/// changing anything here.
 const ARRAY_BUFFER_SIZE: usize = 0x800;
 /// Conservatively give more than enough byte space, so that we only need 1 allocation.
 const CAPACITY: usize = "CracklePop".len() * 100;
 
+/// The exact number of bytes the byte-buffer CracklePop variants write for the
+/// numbers `start..=end`, trailing newline included. Computed in closed form
+/// (counting multiples and digit widths) so it stays cheap to evaluate at
+/// compile time even for huge ranges.
+///
+/// Since the initial allocation of an oversized buffer is what kills perf (see
+/// `ARRAY_BUFFER_SIZE`), this lets us size a buffer exactly instead of
+/// hand-picking a constant and hoping it's big enough.
+///
+/// Panics if the count doesn't fit in a `usize`, which in a const context,
+/// like a `CrackleBuffer` size, is a compile error rather than a buffer that
+/// silently wraps around to the wrong size. See `checked_exact_bytes` for
+/// ranges that might be that big.
+pub const fn exact_bytes(start: u64, end: u64) -> usize {
+    match checked_exact_bytes(start, end) {
+        Some(bytes) => bytes,
+        None => panic!("exact_bytes: the output doesn't fit in a usize"),
+    }
+}
+
+/// `?` for `Option`, which const fns can't use yet.
+macro_rules! const_try {
+    ($e:expr) => {
+        match $e {
+            Some(x) => x,
+            None => return None,
+        }
+    };
+}
+
+/// `exact_bytes`, but `None` when the count doesn't fit in a `usize`.
+pub const fn checked_exact_bytes(start: u64, end: u64) -> Option<usize> {
+    if start > end {
+        return Some(0);
+    }
+
+    let fifteens = multiples_between(15, start, end);
+    let threes = multiples_between(3, start, end) - fifteens;
+    let fives = multiples_between(5, start, end) - fifteens;
+    let mut bytes = const_try!(fifteens.checked_mul("CracklePop".len() as u64));
+    bytes = const_try!(bytes.checked_add(const_try!(threes.checked_mul("Crackle".len() as u64))));
+    bytes = const_try!(bytes.checked_add(const_try!(fives.checked_mul("Pop".len() as u64))));
+
+    // Numbers are summed up one digit-width band at a time: 0..=9, 10..=99...
+    let mut digits = 1;
+    let mut lo = 0;
+    loop {
+        let hi = if digits == 20 {
+            u64::MAX
+        } else {
+            10u64.pow(digits) - 1
+        };
+        let a = if lo > start { lo } else { start };
+        let b = if hi < end { hi } else { end };
+        if a <= b {
+            // Only the first band starts at 0, so `b - a + 1` can't overflow.
+            let plain = (b - a + 1) + multiples_between(15, a, b)
+                - multiples_between(3, a, b)
+                - multiples_between(5, a, b);
+            bytes = const_try!(bytes.checked_add(const_try!(plain.checked_mul(digits as u64))));
+        }
+        if hi >= end {
+            break;
+        }
+        lo = hi + 1;
+        digits += 1;
+    }
+
+    // One newline per line. `end - start + 1` itself overflows for
+    // `0..=u64::MAX`.
+    bytes = const_try!(bytes.checked_add(end - start));
+    bytes = const_try!(bytes.checked_add(1));
+    if bytes > usize::MAX as u64 {
+        return None;
+    }
+    Some(bytes as usize)
+}
+
+/// How many multiples of `k` lie in `a..=b`. Requires `a <= b`.
+const fn multiples_between(k: u64, a: u64, b: u64) -> u64 {
+    let below_a = if a == 0 { 0 } else { (a - 1) / k + 1 };
+    b / k + 1 - below_a
+}
+
+/// The length of the longest word in `rules`, or 0 without any.
+pub const fn longest_word(rules: &[(u64, &str)]) -> usize {
+    let mut longest = 0;
+    let mut i = 0;
+    while i < rules.len() {
+        if rules[i].1.len() > longest {
+            longest = rules[i].1.len();
+        }
+        i += 1;
+    }
+    longest
+}
+
+/// An upper bound on the bytes the configurable engine writes for
+/// `start..=end` with `rules`, so custom words can't overflow a buffer sized
+/// by it. Assumes the `Config` defaults for everything else: no `concat_sep`,
+/// decimal numbers and a `\n` after every line.
+///
+/// Unlike `exact_bytes` this doesn't count anything up. Every line is assumed
+/// to be as long as either the biggest number or every rule matching at once,
+/// with each word as long as the longest one, whichever is longer.
+pub const fn crackle_pop_max_bytes(start: u64, end: u64, rules: &[(u64, &str)]) -> usize {
+    if start > end {
+        return 0;
+    }
+    let words = rules.len() * longest_word(rules);
+    let digits = match end.checked_ilog10() {
+        Some(log) => log as usize + 1,
+        None => 1,
+    };
+    let line = if words > digits { words } else { digits };
+    // Saturating, as a range this big doesn't fit any buffer anyway.
+    ((end - start) as usize)
+        .saturating_add(1)
+        .saturating_mul(line + 1)
+}
+
+/// Expands to an `ArrayBuffer<u8, N>` type where `N` is exactly the number of
+/// bytes CracklePop writes for `start..=end`, so the buffer can neither
+/// overflow nor over-allocate:
+///
+/// ```ignore
+/// let mut buf: crackle_buffer!(1, 100) = ArrayBuffer::new();
+/// ```
+///
+/// This is a macro rather than a `CrackleBuffer<START, END>` type alias since
+/// const arithmetic on generic parameters isn't available without the
+/// incomplete `generic_const_exprs` feature.
+#[allow(unused_macros)] // Currently used in tests.
+macro_rules! crackle_buffer {
+    ($start:expr, $end:expr) => {
+        $crate::ArrayBuffer<u8, { $crate::exact_bytes($start, $end) }>
+    };
+}
+
+/// Like every other variant, ends with a newline after the last line. See the
+/// `engine` module docs for how they used to disagree.
 pub fn crackle_pop() {
+    print!("{}", crackle_pop_string());
+}
+
+/// `crackle_pop`'s output, also used to check `rc_sub` against it.
+fn crackle_pop_string() -> String {
     let mut str = String::with_capacity(CAPACITY);
     for n in 1..=100 {
         let div_by_3 = n % 3 == 0;
@@ -76,7 +246,7 @@ pub fn crackle_pop() {
         }
         str.push('\n');
     }
-    print!("{}", str.trim());
+    str
 }
 
 /// Uses u8's and hardcoded const values rather than string buffer manipulation.
@@ -141,7 +311,7 @@ pub fn crackle_pop_faster_utf8() {
         if str.is_empty() {
             unsafe {
                 write_u8_as_utf8(n, &mut vec);
-                vec.write(b"\n").unwrap();
+                vec.write_all(b"\n").unwrap();
                 print!("{}", str::from_utf8_unchecked(&vec));
                 vec.clear();
             }
@@ -303,7 +473,7 @@ pub fn crackle_pop_vec_minimal_vars() {
         buf.push(b'\n');
     }
 
-    println!("{}", unsafe { str::from_utf8_unchecked(&buf) });
+    print!("{}", unsafe { str::from_utf8_unchecked(&buf) });
 }
 
 /// Doesn't use print, and doesn't use internal allocation.
@@ -431,9 +601,47 @@ fn _crackle_pop_split_up() {
     unimplemented!()
 }
 
+/// The decimal digits of every u8 and how many of them there are, e.g.
+/// `U8_DECIMAL[42] == (*b"42\0", 2)`. Built at compile time.
+const U8_DECIMAL: [([u8; 3], u8); 256] = u8_decimal_table();
+
+const fn u8_decimal_table() -> [([u8; 3], u8); 256] {
+    const UTF8_ZERO: u8 = b'0';
+    let mut table = [([0u8; 3], 0u8); 256];
+    let mut i = 0;
+    while i < 256 {
+        let x = i as u8;
+        table[i] = if x < 10 {
+            ([UTF8_ZERO + x, 0, 0], 1)
+        } else if x < 100 {
+            ([UTF8_ZERO + x / 10, UTF8_ZERO + x % 10, 0], 2)
+        } else {
+            (
+                [
+                    UTF8_ZERO + x / 100,
+                    UTF8_ZERO + x / 10 % 10,
+                    UTF8_ZERO + x % 10,
+                ],
+                3,
+            )
+        };
+        i += 1;
+    }
+    table
+}
+
 /// Encodes a u8 number in utf8 format (for general IO printing), and writes it
-/// to a buffer.
+/// to a buffer. A lookup in `U8_DECIMAL`, so there are no branches and three
+/// digit numbers cost the same as the rest.
 fn write_u8_as_utf8<W: Write>(x: u8, buf: &mut W) {
+    let (digits, len) = &U8_DECIMAL[x as usize];
+    buf.write_all(&digits[..*len as usize]).unwrap();
+}
+
+/// The branching version `write_u8_as_utf8` used before `U8_DECIMAL`, kept to
+/// bench against.
+#[allow(dead_code)] // Currently used in tests.
+fn write_u8_as_utf8_branchy<W: Write>(x: u8, buf: &mut W) {
     const UTF8_ZERO: u8 = b'0';
     if x < 10 {
         buf.write_all(&[UTF8_ZERO + x]).unwrap();
@@ -465,21 +673,247 @@ fn write_1_or_2_digit_u8_as_utf8<W: Write>(x: u8, buf: &mut W) {
     }
 }
 
+/// Encodes any u64 number in utf8 format and writes it to a buffer with a
+/// single write. The digits go through a small stack scratch array, so unlike
+/// `write_u8_as_utf8_branchy` there's no `format!` fallback for big values.
+fn write_u64_as_utf8<W: Write>(x: u64, buf: &mut W) -> io::Result<()> {
+    // u64::MAX is 20 digits long.
+    let mut scratch = [0u8; 20];
+    buf.write_all(format_u64_into(x, &mut scratch).as_bytes())
+}
+
+/// Writes `x` in any radix from 2 to 36, with lowercase letters for the digits
+/// past 9, e.g. `ff` for 255 in hex. Like `write_u64_as_utf8`, it's a single
+/// write from a stack scratch array, big enough for u64::MAX in binary.
+///
+/// Returns an `InvalidInput` error, without writing anything, for a radix
+/// outside of 2..=36.
+pub fn write_uint_radix<W: Write>(x: u64, radix: u32, buf: &mut W) -> io::Result<()> {
+    const DIGITS: &[u8; 36] = b"0123456789abcdefghijklmnopqrstuvwxyz";
+    check_radix(radix)?;
+
+    let radix = u64::from(radix);
+    let mut scratch = [0u8; 64];
+    let mut start = scratch.len();
+    let mut x = x;
+    loop {
+        start -= 1;
+        scratch[start] = DIGITS[(x % radix) as usize];
+        x /= radix;
+        if x == 0 {
+            break;
+        }
+    }
+    buf.write_all(&scratch[start..])
+}
+
+/// Writes `x` in decimal with `digits[d]` standing in for each digit `d`, for
+/// digit sets other than ASCII, e.g. fullwidth `０`..`９`. The glyphs can be any
+/// length; they're written one by one, so stick with `write_u64_as_utf8` for
+/// plain ASCII digits.
+pub fn write_uint_with_digits<W: Write>(
+    x: u64,
+    digits: &[&[u8]; 10],
+    buf: &mut W,
+) -> io::Result<()> {
+    let mut scratch = [0u8; 20];
+    let mut start = scratch.len();
+    let mut x = x;
+    loop {
+        start -= 1;
+        scratch[start] = (x % 10) as u8;
+        x /= 10;
+        if x == 0 {
+            break;
+        }
+    }
+    for &digit in &scratch[start..] {
+        buf.write_all(digits[digit as usize])?;
+    }
+    Ok(())
+}
+
+/// The `InvalidInput` error for a radix outside of 2..=36.
+pub(crate) fn check_radix(radix: u32) -> io::Result<()> {
+    if (2..=36).contains(&radix) {
+        return Ok(());
+    }
+    Err(io::Error::new(
+        io::ErrorKind::InvalidInput,
+        format!("radix must be between 2 and 36, got {}", radix),
+    ))
+}
+
+/// Formats `x` in decimal into the start of `buf` without touching the heap,
+/// and returns the formatted digits. 20 bytes is enough for any u64.
+///
+/// Panics if `x` has more digits than `buf` can hold.
+pub fn format_u64_into<const N: usize>(x: u64, buf: &mut [u8; N]) -> &str {
+    const UTF8_ZERO: u8 = b'0';
+    let mut len = 1;
+    let mut rest = x / 10;
+    while rest > 0 {
+        len += 1;
+        rest /= 10;
+    }
+    assert!(
+        len <= N,
+        "{} has {} digits, which doesn't fit in {} bytes",
+        x,
+        len,
+        N
+    );
+
+    let mut x = x;
+    for i in (0..len).rev() {
+        buf[i] = UTF8_ZERO + (x % 10) as u8;
+        x /= 10;
+    }
+    // Safe, as we've only written ASCII digits.
+    unsafe { str::from_utf8_unchecked(&buf[..len]) }
+}
+
+/// Formats the lowest `N * 4` bits of `x` as exactly `N` lowercase hex digits,
+/// zero padded, into `buf`.
+fn format_hex_into<const N: usize>(x: u64, buf: &mut [u8; N]) -> &str {
+    const HEX_DIGITS: &[u8; 16] = b"0123456789abcdef";
+    let mut x = x;
+    for digit in buf.iter_mut().rev() {
+        *digit = HEX_DIGITS[(x & 0xf) as usize];
+        x >>= 4;
+    }
+    // Safe, as we've only written ASCII hex digits.
+    unsafe { str::from_utf8_unchecked(buf) }
+}
+
+/// Unsigned integers, along with the most decimal digits a value of the type
+/// can have.
+pub trait MaxDigits: Copy + Into<u64> {
+    const MAX_DIGITS: usize;
+}
+
+macro_rules! impl_max_digits {
+    ($($ty:ty),*) => {
+        $(
+            impl MaxDigits for $ty {
+                const MAX_DIGITS: usize = <$ty>::MAX.ilog10() as usize + 1;
+            }
+        )*
+    };
+}
+
+impl_max_digits!(u8, u16, u32, u64);
+
+/// Like `format_u64_into`, but proves that `x` fits at compile time rather
+/// than checking at runtime: naming a `MAX_DIGITS` smaller than the longest
+/// value of `T` fails to compile. So a `u8` gets by with a `[u8; 3]` scratch,
+/// and there's no panicking path left to pay for. The digits are written to
+/// the end of `buf` and returned.
+pub fn write_uint_capped<T: MaxDigits, const MAX_DIGITS: usize>(
+    x: T,
+    buf: &mut [u8; MAX_DIGITS],
+) -> &[u8] {
+    const {
+        assert!(
+            T::MAX_DIGITS <= MAX_DIGITS,
+            "MAX_DIGITS is too small for every value of the integer type"
+        )
+    };
+    const UTF8_ZERO: u8 = b'0';
+
+    let mut x: u64 = x.into();
+    let mut start = MAX_DIGITS;
+    loop {
+        start -= 1;
+        buf[start] = UTF8_ZERO + (x % 10) as u8;
+        x /= 10;
+        if x == 0 {
+            break;
+        }
+    }
+    &buf[start..]
+}
+
+/// Displays a u64 through `format_u64_into` instead of std's integer
+/// formatting, so `format!("{}", DisplayU64(n))` or writing it into any
+/// `fmt::Write` sink goes through our own digit writer. Width, fill and zero
+/// padding flags are still honored.
+#[derive(Debug, Clone, Copy)]
+pub struct DisplayU64(pub u64);
+
+impl fmt::Display for DisplayU64 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut buf = [0u8; 20];
+        f.pad_integral(true, "", format_u64_into(self.0, &mut buf))
+    }
+}
+
+/// Values `ArrayBuffer::append_display` can write. The default goes through
+/// `Display` and `write!`, and the integer types override it with
+/// `format_u64_into`, skipping the formatting machinery entirely. Stands in
+/// for specializing on `Display`, which isn't available.
+pub trait AppendDisplay: fmt::Display {
+    fn append_to<const N: usize>(&self, buf: &mut ArrayBuffer<u8, N>) {
+        write!(buf, "{}", self).unwrap();
+    }
+}
+
+macro_rules! impl_append_display {
+    (unsigned: $($uint:ty),*; signed: $($int:ty),*; display: $($ty:ty),*) => {
+        $(
+            impl AppendDisplay for $uint {
+                fn append_to<const N: usize>(&self, buf: &mut ArrayBuffer<u8, N>) {
+                    let mut scratch = [0; 20];
+                    buf.push_buf(format_u64_into(*self as u64, &mut scratch).as_bytes());
+                }
+            }
+        )*
+        $(
+            impl AppendDisplay for $int {
+                fn append_to<const N: usize>(&self, buf: &mut ArrayBuffer<u8, N>) {
+                    if *self < 0 {
+                        buf.push(b'-');
+                    }
+                    self.unsigned_abs().append_to(buf);
+                }
+            }
+        )*
+        $(impl AppendDisplay for $ty {})*
+    };
+}
+
+impl_append_display!(
+    unsigned: u8, u16, u32, u64, usize;
+    signed: i8, i16, i32, i64, isize;
+    display: str, String, char, bool, f32, f64, DisplayU64
+);
+
+impl<T: AppendDisplay + ?Sized> AppendDisplay for &T {
+    fn append_to<const N: usize>(&self, buf: &mut ArrayBuffer<u8, N>) {
+        (**self).append_to(buf)
+    }
+}
+
 /// This data structure will go directly on the stack. It is only intended to be
 /// written to and consumed. Optimal for smaller IO (otherwise we'd want
 /// dynamic). Barebones and prone to panic-ing.
 ///
-/// No methods will check that writing to the buffer won't overflow. Instead,
-/// Rust will just panic.
+/// The plain write methods don't check that writing to the buffer won't
+/// overflow. Instead, Rust will just panic. The checked ones (`try_push_buf`,
+/// `checked_push`, `write_if_fits`, `try_pad_to`) report it instead, and
+/// leave the buffer as it was.
 ///
 /// This structure allocates up front in FULL. Be mindful to re-use it where
 /// possible rather than creating any large buffers internal to funcs/methods.
 /// Small buffers could be fine. TODO: implement a more dynamic array buffer!
-#[derive(Debug, Clone)]
+#[derive(Clone)]
 pub struct ArrayBuffer<T, const N: usize> {
     /// The current position that we may write to.
     pos: usize,
     buf: [T; N],
+    /// `None` unless `verify_utf8_once` was called, and then how much of the
+    /// written region is already known to be UTF8.
+    utf8_checked: Option<usize>,
 }
 
 impl<T: Default + Copy, const N: usize> ArrayBuffer<T, N> {
@@ -487,171 +921,2544 @@ impl<T: Default + Copy, const N: usize> ArrayBuffer<T, N> {
         ArrayBuffer {
             pos: 0,
             buf: [T::default(); N],
+            utf8_checked: None,
+        }
+    }
+
+    /// Like `new`, but allocated on the heap, for buffers that are too big for
+    /// the stack. `Box::new(ArrayBuffer::new())` won't do: the buffer is
+    /// usually built on the stack first and then moved, so this initializes it
+    /// in place instead.
+    ///
+    /// As a rule of thumb, stick with `new` up to a few KiB; past that the
+    /// up-front cost dominates anyway (see `ARRAY_BUFFER_SIZE`), and at
+    /// hundreds of KiB you're risking overflowing a thread's stack (2 MiB by
+    /// default for spawned threads).
+    pub fn new_boxed() -> Box<Self> {
+        let mut boxed = Box::<Self>::new_uninit();
+        let ptr = boxed.as_mut_ptr();
+        // SAFETY: every field is initialized through raw pointers before
+        // `assume_init`, without creating references to uninitialized memory.
+        unsafe {
+            ptr::addr_of_mut!((*ptr).pos).write(0);
+            ptr::addr_of_mut!((*ptr).utf8_checked).write(None);
+            let buf = ptr::addr_of_mut!((*ptr).buf) as *mut T;
+            for i in 0..N {
+                buf.add(i).write(T::default());
+            }
+            boxed.assume_init()
         }
     }
 
-    fn push_buf(&mut self, buf: &[T]) {
+    /// Appends `buf`. Like every unchecked write, panics if it doesn't fit.
+    pub fn push_buf(&mut self, buf: &[T]) {
         let len = buf.len();
-        for i in 0..len {
-            self.buf[i + self.pos] = buf[i];
+        self.buf[self.pos..self.pos + len].copy_from_slice(buf);
+        self.pos += len;
+    }
+
+    /// Appends `other`, under the name `Vec` uses, so code like
+    /// `crackle_pop_vec_minimal_vars` ports over unchanged.
+    ///
+    /// Panics if `other` doesn't fit; `try_push_buf` is the checked version.
+    pub fn extend_from_slice(&mut self, other: &[T]) {
+        self.push_buf(other);
+    }
+
+    /// The checked version of `push_buf`: if `buf` doesn't fit, nothing is
+    /// written and a `Capacity` error is returned instead of panicking.
+    pub fn try_push_buf(&mut self, buf: &[T]) -> Result<(), CrackleError> {
+        let available = N - self.pos;
+        if buf.len() > available {
+            return Err(CrackleError::Capacity {
+                needed: buf.len(),
+                available,
+            });
+        }
+        self.push_buf(buf);
+        Ok(())
+    }
+
+    /// Moves the written region into a buffer of capacity `M`, typically a
+    /// much smaller one once the output is known, so the oversized backing
+    /// array doesn't have to be carried around. If the written region doesn't
+    /// fit in `M`, `self` is handed back untouched.
+    pub fn shrink<const M: usize>(self) -> Result<ArrayBuffer<T, M>, Self> {
+        if self.pos > M {
+            return Err(self);
         }
+        let mut shrunk = ArrayBuffer::new();
+        shrunk.push_buf(&self.buf[..self.pos]);
+        Ok(shrunk)
+    }
+
+    /// Clones just the written region into a buffer of capacity `M`. The
+    /// derived `Clone` copies all `N` elements, dead tail included, which adds
+    /// up for a big buffer holding little, as when cloning into
+    /// `crackle_pop_ext_owned_arraybuf_minimal_vars`. Returns a `Capacity`
+    /// error if the written region doesn't fit in `M`.
+    pub fn compact_clone<const M: usize>(&self) -> Result<ArrayBuffer<T, M>, CrackleError> {
+        let mut clone = ArrayBuffer::new();
+        clone.try_push_buf(self.as_slice())?;
+        Ok(clone)
+    }
+}
+
+/// Conversions to and from `SmallVec`, for codebases that standardized on it.
+/// Only the written region ever moves across.
+#[cfg(feature = "smallvec")]
+impl<T: Default + Copy, const N: usize> ArrayBuffer<T, N> {
+    /// Moves the elements of `vec` into a new buffer, or returns a `Capacity`
+    /// error if there are more than `N` of them.
+    pub fn from_smallvec<A>(vec: smallvec::SmallVec<A>) -> Result<Self, CrackleError>
+    where
+        A: smallvec::Array<Item = T>,
+    {
+        let mut buf = Self::new();
+        buf.try_push_buf(&vec)?;
+        Ok(buf)
+    }
+
+    /// Moves the written region into a `SmallVec`, which spills onto the heap
+    /// if it doesn't fit inline.
+    pub fn into_smallvec<A>(self) -> smallvec::SmallVec<A>
+    where
+        A: smallvec::Array<Item = T>,
+    {
+        smallvec::SmallVec::from_slice(self.as_slice())
+    }
+}
+
+impl<T: Default + Copy, const N: usize> Default for ArrayBuffer<T, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// In-place editing of the written region. These shift data around with
+/// `copy_within`, hence the `Copy` bound.
+impl<T: Copy, const N: usize> ArrayBuffer<T, N> {
+    /// Removes the elements in `range` from the written region and shifts the
+    /// rest of it down to close the gap. Like `Vec::drain`, but without
+    /// returning an iterator over the removed elements.
+    ///
+    /// Panics if `range` doesn't lie within the written region.
+    pub fn drain(&mut self, range: Range<usize>) {
+        assert!(
+            range.start <= range.end && range.end <= self.pos,
+            "drain range {:?} out of bounds for written length {}",
+            range,
+            self.pos
+        );
+        self.edited_from(range.start);
+        self.buf.copy_within(range.end..self.pos, range.start);
+        self.pos -= range.len();
+    }
+
+    /// Inserts `items` at `at`, shifting the rest of the written region right
+    /// to make room, e.g. to add a header line once the output is generated.
+    ///
+    /// Panics if `at` is past the written region or `items` doesn't fit.
+    pub fn insert(&mut self, at: usize, items: &[T]) {
+        assert!(
+            at <= self.pos,
+            "insert position {} out of bounds for written length {}",
+            at,
+            self.pos
+        );
+        assert!(
+            items.len() <= N - self.pos,
+            "inserting {} elements overflows a buffer with {} left",
+            items.len(),
+            N - self.pos
+        );
+        self.edited_from(at);
+        self.buf.copy_within(at..self.pos, at + items.len());
+        self.buf[at..at + items.len()].copy_from_slice(items);
+        self.pos += items.len();
+    }
+
+    /// Appends a copy of the elements in `range`, which must lie within the
+    /// written region, like `Vec::extend_from_within`. Good for repeating a
+    /// header or delimiter that was written once. `copy_within` has memmove
+    /// semantics, so the source and destination may safely overlap.
+    ///
+    /// Panics if `range` isn't within the written region or the copy doesn't
+    /// fit.
+    pub fn extend_from_within(&mut self, range: Range<usize>) {
+        assert!(
+            range.start <= range.end && range.end <= self.pos,
+            "extend range {:?} out of bounds for written length {}",
+            range,
+            self.pos
+        );
+        let len = range.len();
+        self.buf.copy_within(range, self.pos);
         self.pos += len;
     }
+
+    /// Removes consecutive repeated elements from the written region, like
+    /// `Vec::dedup`, e.g. to collapse runs of blank lines.
+    pub fn dedup(&mut self)
+    where
+        T: PartialEq,
+    {
+        if self.pos == 0 {
+            return;
+        }
+        self.edited_from(0);
+        let mut write = 1;
+        for read in 1..self.pos {
+            if self.buf[read] != self.buf[write - 1] {
+                self.buf[write] = self.buf[read];
+                write += 1;
+            }
+        }
+        self.pos = write;
+    }
+
+    /// Keeps only the last `keep` elements of the written region, moved down
+    /// to the front, and drops everything before them. Keeping a tail like
+    /// this after every write turns the buffer into a sliding window over the
+    /// most recent output. Keeping `len()` or more elements changes nothing.
+    pub fn rotate_and_truncate(&mut self, keep: usize) {
+        if keep >= self.pos {
+            return;
+        }
+        self.edited_from(0);
+        // The source and destination overlap when keep > pos / 2, which
+        // copy_within's memmove semantics handle.
+        self.buf.copy_within(self.pos - keep..self.pos, 0);
+        self.pos = keep;
+    }
 }
 
 impl<T, const N: usize> ArrayBuffer<T, N> {
-    #[allow(dead_code)] // Currently used in tests.
+    /// Uses `arr` as the backing storage of an empty buffer: nothing counts as
+    /// written, so the first push overwrites `arr[0]`. To treat the whole
+    /// array as already written, use `filled` instead.
+    pub fn with_backing(arr: [T; N]) -> Self {
+        ArrayBuffer {
+            pos: 0,
+            buf: arr,
+            utf8_checked: None,
+        }
+    }
+
+    /// Wraps `arr` as a full buffer: all `N` elements count as written, so it
+    /// slices, searches and flushes as the whole of `arr`, and there's no room
+    /// left to push.
+    pub fn filled(arr: [T; N]) -> Self {
+        ArrayBuffer {
+            pos: N,
+            buf: arr,
+            utf8_checked: None,
+        }
+    }
+
+    /// Whether this ends up empty or full is a common surprise, so prefer the
+    /// explicitly named constructors.
+    #[deprecated(note = "use `with_backing` for an empty buffer, or `filled` for a full one")]
     pub fn from(arr: [T; N]) -> Self {
-        ArrayBuffer { pos: 0, buf: arr }
+        Self::with_backing(arr)
     }
 
     pub fn push_fixed<const M: usize>(&mut self, buf: [T; M]) {
         let pos = self.pos;
-        IntoIter::new(buf)
+        IntoIterator::into_iter(buf)
             .enumerate()
             .for_each(|(i, x)| self.buf[pos + i] = x);
         self.pos += M;
     }
 
-    fn push(&mut self, val: T) {
+    /// The checked version of `push_fixed`: pushes `arr` if all of it fits,
+    /// and otherwise hands it back untouched, so it can be pushed again once
+    /// the buffer's been flushed.
+    pub fn try_push_fixed<const M: usize>(&mut self, arr: [T; M]) -> Result<(), [T; M]> {
+        if M > N - self.pos {
+            return Err(arr);
+        }
+        self.push_fixed(arr);
+        Ok(())
+    }
+
+    /// Appends `val`, panicking if the buffer is full. See `checked_push`.
+    pub fn push(&mut self, val: T) {
         self.buf[self.pos] = val;
         self.pos += 1;
     }
-}
 
-impl<const N: usize> ArrayBuffer<u8, N> {
-    /// Attempts to write the entire buffer to stdout. If it fails, the
-    /// operation has to be repeated, as no state is saved internally to track
-    /// what was last printed.
+    /// The checked version of `push`: pushes `val` and returns the new length
+    /// if there was room, otherwise returns `None` and leaves the buffer as it
+    /// was.
+    pub fn checked_push(&mut self, val: T) -> Option<usize> {
+        if self.pos == N {
+            return None;
+        }
+        self.push(val);
+        Some(self.pos)
+    }
+
+    /// The written region, the same as `&self[..]`.
+    pub fn as_slice(&self) -> &[T] {
+        &self.buf[..self.pos]
+    }
+
+    /// A pointer to the start of the backing array, for handing the written
+    /// region to C as `(buf.as_ptr(), buf.len())`. Only the first `len()`
+    /// elements are written output.
     ///
-    /// I can probably update this to write out to any sink, and then write a
-    /// mock stdout for testing to avoid the problem of clobbering the terminal
-    /// with line info. But for now, I've opted for a print! oriented
-    /// implementation.
-    pub fn write_all_to_stdout(&mut self) -> io::Result<()> {
-        // io::stdout().write_all(&self.buf[0..self.pos])?;
-        let str = unsafe { str::from_utf8_unchecked(&self.buf[0..self.pos]) };
-        print!("{}", str);
-        self.pos = 0;
-        Ok(())
+    /// The pointer is only valid for as long as the borrow of `self` it came
+    /// from, and never for more than `N` elements.
+    pub fn as_ptr(&self) -> *const T {
+        self.buf.as_ptr()
     }
 
-    /// Functions identically to pushing a value and then pushing a newline
-    /// character code, but with potentially higher performance.
-    pub fn push_line(&mut self, val: u8) {
-        self.buf[self.pos] = val;
-        self.buf[self.pos + 1] = b'\n';
-        self.pos += 2;
+    /// A mutable pointer to the start of the backing array, for C APIs that
+    /// fill in a buffer. Valid for `N` elements, for as long as the borrow of
+    /// `self` it came from: never write past the end of the array, nor use the
+    /// pointer after touching the buffer some other way. Anything written past
+    /// `len()` only counts once committed with `advance`.
+    ///
+    /// As the written region may change behind its back, this forgets what
+    /// `verify_utf8_once` had already checked.
+    pub fn as_mut_ptr(&mut self) -> *mut T {
+        self.edited_from(0);
+        self.buf.as_mut_ptr()
     }
 
-    /// Functions identically to pushing a buffer and then pushing a newline
-    /// character code, but with potentially higher performance.
-    pub fn push_buf_line(&mut self, buf: &[u8]) {
-        let len = buf.len();
-        for i in 0..len {
-            self.buf[i + self.pos] = buf[i];
-        }
-        self.buf[len + self.pos] = b'\n';
-        self.pos += len + 1;
+    /// The element at `i` if it's been written, unlike indexing which panics.
+    pub fn get(&self, i: usize) -> Option<&T> {
+        self.as_slice().get(i)
     }
 
-    /// A specialized version of this function, working directly through array
-    /// buffer methods rather than the general Write trait. I'm curious about
-    /// potential performance differences.
-    fn write_u8_as_utf8(&mut self, x: u8) {
-        const UTF8_ZERO: u8 = b'0';
-        if x < 10 {
-            self.push(UTF8_ZERO + x);
-        } else if x < 100 {
-            let ones = x % 10;
-            let tens = x / 10;
-            self.push_fixed([UTF8_ZERO + tens, UTF8_ZERO + ones]);
-        } else {
-            let s_buf = format!("{}", x);
-            self.push_buf(s_buf.as_bytes());
+    /// Replaces the element at `i` and returns the old one, for patching up
+    /// already written output. `None` if `i` hasn't been written, in which
+    /// case nothing changes: this never touches the unwritten tail.
+    pub fn set(&mut self, i: usize, val: T) -> Option<T> {
+        if i >= self.pos {
+            return None;
         }
+        self.edited_from(i);
+        Some(std::mem::replace(&mut self.buf[i], val))
     }
 
-    /// A further specialized version that rolls in adding a newline as well.
-    fn write_u8_as_utf8_with_newline(&mut self, x: u8) {
-        const UTF8_ZERO: u8 = b'0';
-        if x < 10 {
-            self.push_line(UTF8_ZERO + x);
-        } else if x < 100 {
-            let ones = x % 10;
-            let tens = x / 10;
-            self.push_fixed([UTF8_ZERO + tens, UTF8_ZERO + ones, b'\n']);
-        } else {
-            let s_buf = format!("{}\n", x);
-            self.push_buf(s_buf.as_bytes());
+    /// Forgets that anything from `i` on was checked to be UTF8, for edits
+    /// that rewrite already written elements. Appending needs no such call,
+    /// since it only ever adds to the unchecked tail. `i` can land in the
+    /// middle of a char; `check_utf8` backs up to its start.
+    fn edited_from(&mut self, i: usize) {
+        if let Some(checked) = &mut self.utf8_checked {
+            *checked = (*checked).min(i);
         }
     }
-}
 
-/// The ArrayBuffer simply derefs to the underlying buffer. We intentionally do
-/// not provide DerefMut, as our buffer relies upon continuous writing to the
-/// end.
-impl<T, const N: usize> Deref for ArrayBuffer<T, N> {
-    type Target = [T; N];
-    fn deref(&self) -> &Self::Target {
-        &self.buf
+    /// Empties the buffer after a flush, along with what was known about it.
+    fn flushed(&mut self) {
+        self.clear();
     }
-}
 
-/// We simply don't handle possibility for overflow and panic instead. A full
-/// write will always be attempted, and only a panic will prevent it.
-impl<const N: usize> Write for ArrayBuffer<u8, N> {
-    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
-        self.push_buf(buf);
-        Ok(buf.len())
+    /// Empties the buffer for reuse. Nothing is zeroed: the old contents just
+    /// stop counting as written.
+    pub fn clear(&mut self) {
+        self.pos = 0;
+        self.edited_from(0);
     }
 
-    fn flush(&mut self) -> io::Result<()> {
-        Ok(())
+    /// Borrows the buffer through a guard that clears it when dropped, so it's
+    /// always empty for the next user, e.g. the next iteration of a loop
+    /// reusing it. The guard derefs to the buffer, and writes into it for
+    /// `u8`s.
+    pub fn scope(&mut self) -> BufferScope<'_, T, N> {
+        BufferScope { buf: self }
     }
-}
 
-#[cfg(test)]
-mod tests {
-    extern crate test;
-    use std::{borrow::Cow, io::Write};
-    use test::Bencher;
+    /// The whole backing array, unwritten tail included. What `Deref` hands
+    /// out, but explicitly asked for.
+    pub fn backing(&self) -> &[T; N] {
+        &self.buf
+    }
 
-    use crate::{ArrayBuffer, ARRAY_BUFFER_SIZE};
+    /// The written region, `[..len]`. The same as `as_slice`, named to pair
+    /// with `spare`.
+    pub fn written(&self) -> &[T] {
+        self.as_slice()
+    }
 
-    #[test]
-    fn array_buffer_works() {
-        use super::ArrayBuffer;
-        let arr = [0u8; 100];
-        let mut ab = ArrayBuffer::from(arr);
+    /// The unwritten tail, `[len..]`. It's initialized, but only to whatever
+    /// the buffer was created with, not to anything that was pushed.
+    pub fn spare(&self) -> &[T] {
+        &self.buf[self.pos..]
+    }
 
-        ab.push(0);
-        ab.push_fixed([1, 2, 3, 4, 5]);
-        ab.push(99);
+    /// How many elements have been written. Unlike `self.deref().len()`, which
+    /// is always `N`.
+    pub fn len(&self) -> usize {
+        self.pos
+    }
 
-        assert_eq!(&ab[0..8], &[0, 1, 2, 3, 4, 5, 99, 0]);
+    pub fn is_empty(&self) -> bool {
+        self.pos == 0
     }
 
-    #[test]
-    fn write_u8_as_utf8_works() {
-        let mut buf = Vec::new();
+    /// How many more elements fit before the buffer is full.
+    pub fn remaining(&self) -> usize {
+        N - self.pos
+    }
 
-        super::write_u8_as_utf8(6, &mut buf);
-        assert_eq!(&buf, "6".as_bytes());
+    /// Whether `additional` more elements fit, for flushing before a batch of
+    /// the unchecked pushes rather than having one of them panic partway.
+    pub fn has_room_for(&self, additional: usize) -> bool {
+        additional <= self.remaining()
+    }
 
-        buf.clear();
+    /// The unwritten tail of the buffer, for handing to an external encoder
+    /// that fills a provided slice and reports how much it wrote. Mirrors
+    /// `Vec::spare_capacity_mut`, except that the tail is always initialized
+    /// (to whatever `new` or `with_backing` put there). Commit the written elements
+    /// with `advance`.
+    pub fn spare_capacity_mut(&mut self) -> &mut [T] {
+        &mut self.buf[self.pos..]
+    }
 
-        super::write_u8_as_utf8(81, &mut buf);
-        assert_eq!(&buf, "81".as_bytes());
+    /// Commits `n` elements written through `spare_capacity_mut`, like
+    /// `Vec::set_len`.
+    ///
+    /// # Safety
+    ///
+    /// `n` must not exceed the length of the spare capacity, and the first `n`
+    /// spare elements must hold meaningful data. For `u8` buffers that means
+    /// valid UTF8, as the stdout flush skips validation.
+    pub unsafe fn advance(&mut self, n: usize) {
+        debug_assert!(n <= N - self.pos);
+        self.pos += n;
+    }
 
-        buf.clear();
+    /// The safe version of `spare_capacity_mut` and `advance`: hands the next
+    /// `max` elements to `f`, which returns how many of them it wrote, and
+    /// commits that many. Panics if `max` elements don't fit, or if `f` claims
+    /// to have written more than `max`.
+    pub fn write_with(&mut self, max: usize, f: impl FnOnce(&mut [T]) -> usize) {
+        let n = f(&mut self.buf[self.pos..self.pos + max]);
+        assert!(n <= max, "wrote {} elements into {}", n, max);
+        self.pos += n;
+    }
+}
 
-        super::write_u8_as_utf8(240, &mut buf);
-        assert_eq!(&buf, "240".as_bytes());
+impl<const N: usize> ArrayBuffer<u8, N> {
+    /// `new` for bytes, but usable in const contexts, where `T::default()`
+    /// isn't: `static` and `const` buffers, or other const fns.
+    pub const fn new_zeroed() -> Self {
+        ArrayBuffer {
+            pos: 0,
+            buf: [0; N],
+            utf8_checked: None,
+        }
     }
 
-    #[bench]
-    fn concat_vs_hardcoded_cow(b: &mut Bencher) {
+    /// Writes `value` the way `Display` would, but integers go through the
+    /// crate's own encoder instead of `write!`. Panics if it doesn't fit.
+    pub fn append_display<V: AppendDisplay + ?Sized>(&mut self, value: &V) {
+        value.append_to(self);
+    }
+
+    /// Reads up to `N` bytes from `reader` into a new buffer, stopping early
+    /// only at end of file, and treats everything read as written. If the
+    /// reader has more than `N` bytes, the first `N` are read and the rest
+    /// are left in the reader, so a full buffer means there may be more.
+    ///
+    /// The bytes aren't checked to be UTF8, so check them before flushing with
+    /// `write_all_to_stdout`, which assumes they are.
+    pub fn from_reader<R: Read>(reader: &mut R) -> io::Result<Self> {
+        let mut buf = Self::new();
+        while buf.pos < N {
+            match reader.read(&mut buf.buf[buf.pos..]) {
+                Ok(0) => break,
+                Ok(n) => buf.pos += n,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(buf)
+    }
+
+    /// Makes the flushes check that what they write is UTF8, rather than
+    /// trusting the encoders, and fail with `InvalidData` if it isn't. Each
+    /// byte is only checked once: the flushes remember how much of the buffer
+    /// they've checked, and emptying the buffer resets that to nothing.
+    /// Appending only leaves a new unchecked tail, while any method that
+    /// rewrites already written bytes (`set`, `drain`, `insert`, `dedup`,
+    /// `rotate_and_truncate`, `normalize_newlines`) forgets everything from
+    /// the start of the first char it touches on. `write_all_to_vec` can't fail, so it
+    /// never checks.
+    ///
+    /// With nothing but the crate's own encoders writing, this only ever
+    /// costs time; it's for buffers that also hold bytes from elsewhere, like
+    /// `from_reader`.
+    pub fn verify_utf8_once(&mut self) {
+        self.utf8_checked.get_or_insert(0);
+    }
+
+    /// Checks whatever hasn't been checked yet, if `verify_utf8_once` is on.
+    fn check_utf8(&mut self) -> io::Result<()> {
+        if let Some(checked) = self.utf8_checked {
+            // An edit can leave `checked` in the middle of a char, with only
+            // its first bytes untouched, so back up to the start of that char.
+            // The bytes before it are still the valid UTF8 they were when
+            // checked, so the rest can be checked on its own.
+            let mut checked = checked.min(self.pos);
+            while checked > 0 && self.buf[checked - 1] & 0xC0 == 0x80 {
+                checked -= 1;
+            }
+            if checked > 0 && self.buf[checked - 1] >= 0xC0 {
+                checked -= 1;
+            }
+            str::from_utf8(&self.buf[checked..self.pos])
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+            self.utf8_checked = Some(self.pos);
+        }
+        Ok(())
+    }
+
+    /// Attempts to write the entire buffer to stdout. If it fails, the
+    /// operation has to be repeated, as no state is saved internally to track
+    /// what was last printed.
+    ///
+    /// This stays print! oriented so that the tests capture its output; use
+    /// `write_all_to` to write out to any other sink.
+    pub fn write_all_to_stdout(&mut self) -> io::Result<()> {
+        self.check_utf8()?;
+        // io::stdout().write_all(&self.buf[0..self.pos])?;
+        let str = unsafe { str::from_utf8_unchecked(&self.buf[0..self.pos]) };
+        trace::flush("print", str.len(), N, || {
+            print!("{}", str);
+            Ok(())
+        })?;
+        self.flushed();
+        Ok(())
+    }
+
+    /// Writes the entire buffer to `out` and empties it. If the write fails,
+    /// the buffer is left as it was and the whole operation has to be
+    /// repeated, as we can't know how much `write_all` got out before failing.
+    /// `Interrupted` writes are retried rather than counted as failures.
+    pub fn write_all_to<W: Write>(&mut self, out: &mut W) -> io::Result<()> {
+        self.check_utf8()?;
+        let written = &self.buf[..self.pos];
+        trace::flush("writer", written.len(), N, || {
+            write_all_retrying(out, written)
+        })?;
+        self.flushed();
+        Ok(())
+    }
+
+    /// Like `write_all_to`, but gives up once `deadline` has passed, for
+    /// flushing to a slow sink under a time budget. The clock is checked before
+    /// every write, and each write hands `out` at most 512 bytes, so a single
+    /// call only overshoots the deadline by one such write.
+    ///
+    /// Unlike `write_all_to`, a failed flush can be resumed: whatever made it
+    /// out is dropped from the front of the buffer, so calling this again
+    /// carries on where it stopped. Running out of time fails with
+    /// `WouldBlock`; any other error from `out` is passed on the same way.
+    pub fn write_all_to_deadline<W: Write>(
+        &mut self,
+        out: &mut W,
+        deadline: Instant,
+    ) -> io::Result<()> {
+        const CHUNK: usize = 512;
+        self.check_utf8()?;
+        let mut written = 0;
+        let result = loop {
+            if written == self.pos {
+                break Ok(());
+            }
+            if Instant::now() >= deadline {
+                break Err(io::Error::new(
+                    io::ErrorKind::WouldBlock,
+                    "flush deadline passed",
+                ));
+            }
+            let end = self.pos.min(written + CHUNK);
+            match out.write(&self.buf[written..end]) {
+                Ok(0) => {
+                    break Err(io::Error::new(
+                        io::ErrorKind::WriteZero,
+                        "failed to write whole buffer",
+                    ))
+                }
+                Ok(n) => written += n,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+                Err(err) => break Err(err),
+            }
+        };
+        self.drain(0..written);
+        result
+    }
+
+    /// `write_all_to` specialized for a `Vec` sink: reserves room for the
+    /// whole buffer up front and appends it directly, without going through
+    /// `Write`. Can't fail, short of running out of memory.
+    pub fn write_all_to_vec(&mut self, out: &mut Vec<u8>) {
+        out.reserve(self.pos);
+        out.extend_from_slice(self.as_slice());
+        self.flushed();
+    }
+
+    /// Like `write_all_to_stdout`, but skips `print!`, the stdout lock and its
+    /// line buffering entirely, and goes straight to a `write(2)` on fd 1. A
+    /// buffer this size is written in a single syscall in practice.
+    ///
+    /// Anything `print!`ed earlier may still be sitting in std's stdout buffer,
+    /// so flush stdout first when mixing the two, or the output can come out of
+    /// order. Writes here also aren't captured by the test harness.
+    #[cfg(unix)]
+    pub fn write_all_to_stdout_raw(&mut self) -> io::Result<()> {
+        use std::fs::File;
+        use std::mem::ManuallyDrop;
+        use std::os::unix::io::FromRawFd;
+
+        // ManuallyDrop, since dropping the File would close stdout.
+        let mut stdout = ManuallyDrop::new(unsafe { File::from_raw_fd(1) });
+        self.check_utf8()?;
+        let written = &self.buf[..self.pos];
+        trace::flush("raw stdout", written.len(), N, || stdout.write_all(written))?;
+        self.flushed();
+        Ok(())
+    }
+
+    /// Functions identically to pushing a value and then pushing a newline
+    /// character code, but with potentially higher performance.
+    pub fn push_line(&mut self, val: u8) {
+        self.buf[self.pos] = val;
+        self.buf[self.pos + 1] = b'\n';
+        self.pos += 2;
+    }
+
+    /// Functions identically to pushing a buffer and then pushing a newline
+    /// character code, but with potentially higher performance.
+    pub fn push_buf_line(&mut self, buf: &[u8]) {
+        let len = buf.len();
+        self.buf[self.pos..self.pos + len].copy_from_slice(buf);
+        self.buf[len + self.pos] = b'\n';
+        self.pos += len + 1;
+    }
+
+    /// Pushes as much of `buf` as fits and drops the rest, returning how many
+    /// bytes were dropped (0 when everything fit). Never panics. Not to be
+    /// confused with `Write::write_all`, which either writes everything or
+    /// returns an error.
+    pub fn write_all_lossy(&mut self, buf: &[u8]) -> usize {
+        let fits = buf.len().min(N - self.pos);
+        self.buf[self.pos..self.pos + fits].copy_from_slice(&buf[..fits]);
+        self.pos += fits;
+        buf.len() - fits
+    }
+
+    /// Pushes as much of `buf` as fits and silently drops the rest, without
+    /// even saying how much: `write_all_lossy` for when nobody's going to
+    /// look. Never panics and never allocates, so it suits fire-and-forget
+    /// diagnostic buffers, where losing the end of an overlong message beats
+    /// crashing over it. A full buffer just stays full.
+    pub fn saturating_push_buf(&mut self, buf: &[u8]) {
+        self.write_all_lossy(buf);
+    }
+
+    /// The lightest checked write: pushes all of `buf` and returns `true` if it
+    /// fits, otherwise writes nothing at all and returns `false`. Convenient
+    /// for flush-and-retry loops.
+    pub fn write_if_fits(&mut self, buf: &[u8]) -> bool {
+        if self.pos + buf.len() > N {
+            return false;
+        }
+        self.push_buf(buf);
+        true
+    }
+
+    /// Pushes `fill` until `len` bytes are written, for fixed-width columns
+    /// and records. Does nothing if `len` bytes or more are already written.
+    ///
+    /// Panics if `len` is past the end of the buffer; see `try_pad_to`.
+    pub fn pad_to(&mut self, len: usize, fill: u8) {
+        if self.pos < len {
+            self.buf[self.pos..len].fill(fill);
+            self.pos = len;
+        }
+    }
+
+    /// The checked version of `pad_to`: if the padding doesn't fit, nothing is
+    /// written and a `Capacity` error is returned instead of panicking.
+    pub fn try_pad_to(&mut self, len: usize, fill: u8) -> Result<(), CrackleError> {
+        if len > N {
+            return Err(CrackleError::Capacity {
+                needed: len.saturating_sub(self.pos),
+                available: N - self.pos,
+            });
+        }
+        self.pad_to(len, fill);
+        Ok(())
+    }
+
+    /// Writes `nums` separated by `sep`, with no separator after the last one,
+    /// e.g. a comma separated row. Each number goes through `format_u64_into`
+    /// with its separator tacked onto the same scratch buffer, so there's only
+    /// one push per number. An empty slice writes nothing.
+    pub fn write_separated(&mut self, nums: &[u64], sep: u8) {
+        let mut scratch = [0u8; 21];
+        for (i, &n) in nums.iter().enumerate() {
+            let mut len = format_u64_into(n, &mut scratch).len();
+            if i + 1 < nums.len() {
+                scratch[len] = sep;
+                len += 1;
+            }
+            self.push_buf(&scratch[..len]);
+        }
+    }
+
+    /// Fills the rest of the buffer with back to back copies of `pattern`,
+    /// starting from its first byte at the current position. When the space
+    /// left isn't a multiple of `pattern.len()`, the last copy is cut off
+    /// mid-pattern exactly at the end of the buffer. Afterwards the buffer is
+    /// full. An empty pattern writes nothing. Meant for generating benchmark
+    /// data without a real CracklePop run.
+    pub fn fill_with_pattern(&mut self, pattern: &[u8]) {
+        if pattern.is_empty() {
+            return;
+        }
+        for chunk in self.buf[self.pos..].chunks_mut(pattern.len()) {
+            chunk.copy_from_slice(&pattern[..chunk.len()]);
+        }
+        self.pos = N;
+    }
+
+    /// Iterates over the written region in chunks of `size` bytes, exactly
+    /// like `slice::chunks`: every chunk except possibly the last one is
+    /// `size` bytes long. Handy for framing output for a transport with a
+    /// maximum packet size.
+    ///
+    /// Panics if `size` is 0.
+    pub fn chunks(&self, size: usize) -> impl Iterator<Item = &[u8]> {
+        self.buf[..self.pos].chunks(size)
+    }
+
+    /// Borrows the written region as two halves, `[..mid]` and `[mid..]`, like
+    /// `slice::split_at`. The unwritten tail is never part of the second half.
+    ///
+    /// Panics if `mid` is past the written region.
+    pub fn split_at(&self, mid: usize) -> (&[u8], &[u8]) {
+        assert!(
+            mid <= self.pos,
+            "split index {} out of bounds for written length {}",
+            mid,
+            self.pos
+        );
+        self.buf[..self.pos].split_at(mid)
+    }
+
+    /// The `n`th line (counting from 0) of the written region, without its
+    /// newline, or `None` past the last line. A trailing newline ends the last
+    /// line rather than starting an empty one, so for CracklePop output line
+    /// `n` is the one for the number `n + 1`.
+    pub fn nth_line(&self, n: usize) -> Option<&[u8]> {
+        let written = &self.buf[..self.pos];
+        if written.is_empty() {
+            return None;
+        }
+        let lines = written.strip_suffix(b"\n").unwrap_or(written);
+        lines.split(|&b| b == b'\n').nth(n)
+    }
+
+    /// The written region as a `str`, for keeping output around in a cache:
+    /// borrowed when it's valid UTF8, as it always is when only the crate's
+    /// encoders wrote it, and only copied into an owned `String`, with
+    /// invalid sequences replaced by U+FFFD, when it isn't.
+    pub fn to_cow(&self) -> std::borrow::Cow<'_, str> {
+        String::from_utf8_lossy(self.as_slice())
+    }
+
+    /// Calls `f` with every line of the written region, without its newline,
+    /// borrowed straight from the buffer, e.g. to route lines to different
+    /// sinks once the output is built. Lines are split the same way as for
+    /// `nth_line`: a trailing newline doesn't start an extra empty line.
+    pub fn for_each_line<F: FnMut(&[u8])>(&self, f: F) {
+        let written = &self.buf[..self.pos];
+        if written.is_empty() {
+            return;
+        }
+        let lines = written.strip_suffix(b"\n").unwrap_or(written);
+        lines.split(|&b| b == b'\n').for_each(f);
+    }
+
+    /// Whether the written region is exactly `other`.
+    pub fn eq_bytes(&self, other: &[u8]) -> bool {
+        self.as_slice() == other
+    }
+
+    /// Whether the written region is exactly the bytes of `s`, which reads
+    /// better than comparing slices in assertions:
+    /// `assert!(buf.eq_str("Crackle\n"))`.
+    pub fn eq_str(&self, s: &str) -> bool {
+        self.eq_bytes(s.as_bytes())
+    }
+
+    /// Rewrites every `\r\n`, and any stray `\r`, in the written region as a
+    /// plain `\n`, compacting the rest down to close the gaps. For text
+    /// loaded with `from_reader` that mixes line endings.
+    pub fn normalize_newlines(&mut self) {
+        self.edited_from(0);
+        // The write index never passes the read index, as the output is never
+        // longer than the input, so this can go front to back in place.
+        let mut write = 0;
+        let mut read = 0;
+        while read < self.pos {
+            let b = self.buf[read];
+            read += 1;
+            if b == b'\r' {
+                if read < self.pos && self.buf[read] == b'\n' {
+                    read += 1;
+                }
+                self.buf[write] = b'\n';
+            } else {
+                self.buf[write] = b;
+            }
+            write += 1;
+        }
+        self.pos = write;
+    }
+
+    /// Writes the written region for humans: printable ASCII as is, backslashes
+    /// doubled, and every other byte (control bytes, newlines included, and
+    /// anything past ASCII) as `\xNN`. Good for logging output that might hold
+    /// anything.
+    pub fn write_escaped<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        let mut hex_digits = [0u8; 2];
+        // Printable runs go out in one write rather than byte by byte.
+        for run in self
+            .as_slice()
+            .split_inclusive(|&b| b == b'\\' || !(b' '..=b'~').contains(&b))
+        {
+            let (&last, printable) = run.split_last().unwrap();
+            out.write_all(printable)?;
+            if last == b'\\' {
+                out.write_all(b"\\\\")?;
+            } else if (b' '..=b'~').contains(&last) {
+                out.write_all(&[last])?;
+            } else {
+                out.write_all(b"\\x")?;
+                out.write_all(format_hex_into(last.into(), &mut hex_digits).as_bytes())?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes a classic offset, hex and ASCII dump of the written region,
+    /// 16 bytes per line, like `hexdump -C`:
+    ///
+    /// ```text
+    /// 00000000  31 0a 32 0a 43 72 61 63  6b 6c 65 0a 34 0a 50 6f  |1.2.Crackle.4.Po|
+    /// ```
+    ///
+    /// Bytes outside of printable ASCII show up as `.` in the ASCII column.
+    pub fn hex_dump<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        let (mut offset_digits, mut byte_digits) = ([0u8; 8], [0u8; 2]);
+        for (line, bytes) in self.as_slice().chunks(16).enumerate() {
+            out.write_all(format_hex_into((line * 16) as u64, &mut offset_digits).as_bytes())?;
+            out.write_all(b" ")?;
+            for i in 0..16 {
+                if i % 8 == 0 {
+                    out.write_all(b" ")?;
+                }
+                match bytes.get(i) {
+                    Some(&b) => {
+                        out.write_all(format_hex_into(b.into(), &mut byte_digits).as_bytes())?;
+                        out.write_all(b" ")?;
+                    }
+                    None => out.write_all(b"   ")?,
+                }
+            }
+            out.write_all(b" |")?;
+            for &b in bytes {
+                let shown = if b.is_ascii_graphic() || b == b' ' {
+                    b
+                } else {
+                    b'.'
+                };
+                out.write_all(&[shown])?;
+            }
+            out.write_all(b"|\n")?;
+        }
+        Ok(())
+    }
+
+    /// Writes the written region base64 encoded, with the standard alphabet
+    /// and `=` padding, and no line breaks. An empty buffer writes nothing.
+    pub fn write_base64<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        const ALPHABET: &[u8; 64] =
+            b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+        // Encoded 384 bytes at a time, to keep the writes few without
+        // allocating.
+        let mut encoded = [0u8; 512];
+        for input in self.as_slice().chunks(384) {
+            let mut len = 0;
+            for group in input.chunks(3) {
+                let bits = match *group {
+                    [a, b, c] => u32::from_be_bytes([0, a, b, c]),
+                    [a, b] => u32::from_be_bytes([0, a, b, 0]),
+                    [a] => u32::from_be_bytes([0, a, 0, 0]),
+                    _ => unreachable!(),
+                };
+                for (i, digit) in encoded[len..len + 4].iter_mut().enumerate() {
+                    *digit = if i <= group.len() {
+                        ALPHABET[(bits >> (18 - 6 * i) & 0x3f) as usize]
+                    } else {
+                        b'='
+                    };
+                }
+                len += 4;
+            }
+            out.write_all(&encoded[..len])?;
+        }
+        Ok(())
+    }
+
+    /// The position of the first occurrence of `needle` in the written region,
+    /// if any. An empty needle is found at 0, like `str::find`. This is a plain
+    /// naive search, which is plenty for output of this size.
+    pub fn find(&self, needle: &[u8]) -> Option<usize> {
+        let haystack = &self.buf[..self.pos];
+        match needle {
+            [] => Some(0),
+            [byte] => haystack.iter().position(|b| b == byte),
+            _ => haystack
+                .windows(needle.len())
+                .position(|window| window == needle),
+        }
+    }
+
+    /// Whether `needle` occurs anywhere in the written region.
+    pub fn contains(&self, needle: &[u8]) -> bool {
+        self.find(needle).is_some()
+    }
+
+    /// A specialized version of this function, working directly through array
+    /// buffer methods rather than the general Write trait. I'm curious about
+    /// potential performance differences. Like the free function it's a
+    /// `U8_DECIMAL` lookup, so three digits don't allocate either.
+    pub fn write_u8_as_utf8(&mut self, x: u8) {
+        let (digits, len) = &U8_DECIMAL[x as usize];
+        self.push_buf(&digits[..*len as usize]);
+    }
+
+    /// A further specialized version that rolls in adding a newline as well.
+    pub fn write_u8_as_utf8_with_newline(&mut self, x: u8) {
+        self.write_u8_as_utf8_with_sep(x, b'\n');
+    }
+
+    /// `write_u8_as_utf8_with_newline` with any separator after the number,
+    /// such as `b','`. Every length, three digits included, goes out as a
+    /// single fused `push_fixed` with the separator rolled in.
+    pub fn write_u8_as_utf8_with_sep(&mut self, x: u8, sep: u8) {
+        let ([a, b, c], len) = U8_DECIMAL[x as usize];
+        match len {
+            1 => self.push_fixed([a, sep]),
+            2 => self.push_fixed([a, b, sep]),
+            _ => self.push_fixed([a, b, c, sep]),
+        }
+    }
+}
+
+/// Shows only the written region rather than the derived dump of the whole
+/// backing array with its zeroed tail, as a byte string for bytes:
+/// `ArrayBuffer { pos: 4, capacity: 2048, written: b"Pop\n" }`. Other element
+/// types get a list, `written: [80, 111]`. There's no specializing the impl
+/// for `u8`, so it's picked out at runtime instead, which is what the
+/// `'static` bound is for.
+impl<T: fmt::Debug + 'static, const N: usize> fmt::Debug for ArrayBuffer<T, N> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        struct ByteStr<'a>(&'a [u8]);
+        impl fmt::Debug for ByteStr<'_> {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                write!(f, "b\"{}\"", self.0.escape_ascii())
+            }
+        }
+
+        let mut s = f.debug_struct("ArrayBuffer");
+        s.field("pos", &self.pos).field("capacity", &N);
+        match (&self.buf as &dyn Any).downcast_ref::<[u8; N]>() {
+            Some(bytes) => s.field("written", &ByteStr(&bytes[..self.pos])),
+            None => s.field("written", &self.as_slice()),
+        };
+        s.finish()
+    }
+}
+
+/// The ArrayBuffer simply derefs to the underlying buffer. We intentionally do
+/// not provide DerefMut, as our buffer relies upon continuous writing to the
+/// end.
+///
+/// That includes the unwritten tail, which holds real `T::default()` values
+/// (zeros, for bytes) that look meaningful but aren't.
+///
+/// Deprecated: use `written` or `spare` to say which of the two regions you
+/// mean, or `backing` for the whole array. `#[deprecated]` can't go on a trait
+/// impl, so this is the only warning there is, but nothing in the crate goes
+/// through `Deref` anymore and new code shouldn't either. It's only kept so
+/// existing callers don't break.
+impl<T, const N: usize> Deref for ArrayBuffer<T, N> {
+    type Target = [T; N];
+    fn deref(&self) -> &Self::Target {
+        &self.buf
+    }
+}
+
+/// Comparing against bytes or a string compares the written region, so tests
+/// can `assert_eq!(buf, "Crackle\n")`.
+impl<const N: usize> PartialEq<[u8]> for ArrayBuffer<u8, N> {
+    fn eq(&self, other: &[u8]) -> bool {
+        self.as_slice() == other
+    }
+}
+
+impl<const N: usize, const M: usize> PartialEq<[u8; M]> for ArrayBuffer<u8, N> {
+    fn eq(&self, other: &[u8; M]) -> bool {
+        self.as_slice() == other
+    }
+}
+
+impl<const N: usize> PartialEq<str> for ArrayBuffer<u8, N> {
+    fn eq(&self, other: &str) -> bool {
+        self.as_slice() == other.as_bytes()
+    }
+}
+
+impl<const N: usize> PartialEq<&str> for ArrayBuffer<u8, N> {
+    fn eq(&self, other: &&str) -> bool {
+        self.as_slice() == other.as_bytes()
+    }
+}
+
+/// Indexing an ArrayBuffer only ever covers the written region: an index or a
+/// range reaching past it panics instead of silently handing back the default
+/// values of the unwritten tail.
+macro_rules! impl_written_region_index {
+    ($($index:ty => $output:ty),*) => {
+        $(
+            impl<T, const N: usize> Index<$index> for ArrayBuffer<T, N> {
+                type Output = $output;
+                fn index(&self, index: $index) -> &$output {
+                    &self.buf[..self.pos][index]
+                }
+            }
+        )*
+    };
+}
+
+impl_written_region_index!(
+    usize => T,
+    Range<usize> => [T],
+    RangeTo<usize> => [T],
+    RangeFrom<usize> => [T],
+    RangeFull => [T]
+);
+
+/// We simply don't handle possibility for overflow and panic instead. A full
+/// write will always be attempted, and only a panic will prevent it.
+impl<const N: usize> Write for ArrayBuffer<u8, N> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.push_buf(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<const N: usize> ArrayBuffer<u8, N> {
+    /// A `Write` adapter that errors instead of panicking when the buffer
+    /// fills up, for code that only knows about `io::Write`.
+    pub fn guarded_writer(&mut self) -> WriteGuard<'_, N> {
+        WriteGuard { buf: self }
+    }
+}
+
+/// See `ArrayBuffer::guarded_writer`. Writes go into the buffer as far as they
+/// fit, and a write to a full buffer fails with `ErrorKind::WriteZero`, so
+/// `write_all` and `write!` report running out of room as a regular
+/// `io::Error`. Whatever fit before that stays written.
+#[derive(Debug)]
+pub struct WriteGuard<'a, const N: usize> {
+    buf: &'a mut ArrayBuffer<u8, N>,
+}
+
+impl<const N: usize> Write for WriteGuard<'_, N> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if !buf.is_empty() && self.buf.remaining() == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::WriteZero,
+                "ArrayBuffer is full",
+            ));
+        }
+        let dropped = self.buf.write_all_lossy(buf);
+        Ok(buf.len() - dropped)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// `Write::write_all`, spelled out: retries on `Interrupted` and fails with
+/// `WriteZero` if `out` stops taking bytes. Sinks are free to override
+/// `write_all`, and not every override retries, so the flushes use this rather
+/// than trusting it.
+fn write_all_retrying<W: Write>(out: &mut W, mut bytes: &[u8]) -> io::Result<()> {
+    while !bytes.is_empty() {
+        match out.write(bytes) {
+            Ok(0) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::WriteZero,
+                    "failed to write whole buffer",
+                ))
+            }
+            Ok(n) => bytes = &bytes[n..],
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(())
+}
+
+/// See `ArrayBuffer::scope`.
+pub struct BufferScope<'a, T, const N: usize> {
+    buf: &'a mut ArrayBuffer<T, N>,
+}
+
+impl<T: fmt::Debug + 'static, const N: usize> fmt::Debug for BufferScope<'_, T, N> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("BufferScope").field(&self.buf).finish()
+    }
+}
+
+impl<T, const N: usize> Deref for BufferScope<'_, T, N> {
+    type Target = ArrayBuffer<T, N>;
+    fn deref(&self) -> &Self::Target {
+        self.buf
+    }
+}
+
+impl<T, const N: usize> DerefMut for BufferScope<'_, T, N> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.buf
+    }
+}
+
+impl<T, const N: usize> Drop for BufferScope<'_, T, N> {
+    fn drop(&mut self) {
+        self.buf.clear();
+    }
+}
+
+impl<const N: usize> Write for BufferScope<'_, u8, N> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buf.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// UTF-16 output, for APIs that want it, like most of Windows'. Everything
+/// pushed is encoded as UTF-16 code units up front, so flushing only has to
+/// split them into bytes.
+impl<const N: usize> ArrayBuffer<u16, N> {
+    /// Appends already encoded code units.
+    pub fn push_units(&mut self, units: &[u16]) {
+        self.push_buf(units);
+    }
+
+    /// Appends `s` encoded as UTF-16.
+    pub fn push_str(&mut self, s: &str) {
+        for unit in s.encode_utf16() {
+            self.push(unit);
+        }
+    }
+
+    /// Appends `x` in decimal, one code unit per digit.
+    pub fn push_u64(&mut self, x: u64) {
+        let mut digits = [0; 20];
+        for &digit in format_u64_into(x, &mut digits).as_bytes() {
+            self.push(u16::from(digit));
+        }
+    }
+
+    /// Writes the buffer to `out` as UTF-16LE bytes, without a byte order
+    /// mark, and empties it. The bytes go out through a small stack buffer, so
+    /// if a write fails part of the output may already be out, but the buffer
+    /// is left as it was.
+    pub fn write_all_to<W: Write>(&mut self, out: &mut W) -> io::Result<()> {
+        let mut bytes = [0; 512];
+        for units in self.as_slice().chunks(bytes.len() / 2) {
+            for (pair, unit) in bytes.chunks_exact_mut(2).zip(units) {
+                pair.copy_from_slice(&unit.to_le_bytes());
+            }
+            write_all_retrying(out, &bytes[..units.len() * 2])?;
+        }
+        self.flushed();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate test;
+    use std::{
+        borrow::Cow,
+        io::{self, Write},
+    };
+    use test::Bencher;
+
+    use crate::{ArrayBuffer, CrackleError, ARRAY_BUFFER_SIZE};
+
+    /// Stands in for stdout: records every byte written to it, so that output
+    /// can be asserted on instead of clobbering the terminal.
+    #[derive(Debug, Default)]
+    struct MockSink {
+        written: Vec<u8>,
+    }
+
+    impl Write for MockSink {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.written.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn array_buffer_works() {
+        use super::ArrayBuffer;
+        let arr = [0u8; 100];
+        let mut ab = ArrayBuffer::with_backing(arr);
+
+        ab.push(0);
+        ab.push_fixed([1, 2, 3, 4, 5]);
+        ab.push(99);
+
+        assert_eq!(&ab[0..7], &[0, 1, 2, 3, 4, 5, 99]);
+        // Past the written region, only the untouched backing array is left.
+        assert_eq!(ab.buf[7], 0);
+    }
+
+    #[test]
+    fn array_buffer_filled_counts_everything_as_written() {
+        let mut ab = ArrayBuffer::filled(*b"CracklePop");
+        assert_eq!(&ab[..], b"CracklePop");
+        assert!(ab.contains(b"Pop"));
+        assert!(!ab.write_if_fits(b"!"));
+
+        let ab = ArrayBuffer::with_backing(*b"CracklePop");
+        assert_eq!(&ab[..], b"");
+    }
+
+    #[test]
+    fn array_buffer_slicing_is_clamped_to_written_region() {
+        let mut ab: ArrayBuffer<u8, 16> = ArrayBuffer::new();
+        ab.push_buf_line(b"Pop");
+
+        assert_eq!(&ab[..], b"Pop\n");
+        assert_eq!(&ab[1..], b"op\n");
+        assert_eq!(&ab[..3], b"Pop");
+        assert_eq!(&ab[1..3], b"op");
+        assert_eq!(ab[3], b'\n');
+    }
+
+    #[test]
+    #[should_panic]
+    fn array_buffer_slicing_past_written_region_panics() {
+        let mut ab: ArrayBuffer<u8, 16> = ArrayBuffer::new();
+        ab.push_buf_line(b"Pop");
+        let _ = &ab[0..5];
+    }
+
+    #[test]
+    fn array_buffer_chunks_only_covers_written_region() {
+        let mut ab: ArrayBuffer<u8, 16> = ArrayBuffer::new();
+        ab.push_buf_line(b"Crackle");
+        ab.push_buf_line(b"Pop");
+
+        let chunks: Vec<&[u8]> = ab.chunks(5).collect();
+        assert_eq!(chunks, [&b"Crack"[..], b"le\nPo", b"p\n"]);
+    }
+
+    #[test]
+    fn array_buffer_extend_from_within() {
+        let mut ab: ArrayBuffer<u8, 16> = ArrayBuffer::new();
+        ab.push_buf(b"--Pop");
+
+        ab.extend_from_within(0..2);
+        assert_eq!(&ab[..], b"--Pop--");
+
+        // The source runs right up to where the copy lands.
+        ab.extend_from_within(2..7);
+        assert_eq!(&ab[..], b"--Pop--Pop--");
+    }
+
+    #[test]
+    #[should_panic]
+    fn array_buffer_extend_from_within_past_capacity_panics() {
+        let mut ab: ArrayBuffer<u8, 6> = ArrayBuffer::new();
+        ab.push_buf(b"Pop\n");
+        ab.extend_from_within(0..4);
+    }
+
+    #[test]
+    fn array_buffer_split_at_excludes_unwritten_tail() {
+        let mut ab: ArrayBuffer<u8, 16> = ArrayBuffer::new();
+        ab.push_buf_line(b"1");
+        ab.push_buf_line(b"2");
+
+        assert_eq!(ab.split_at(2), (&b"1\n"[..], &b"2\n"[..]));
+        assert_eq!(ab.split_at(4), (&b"1\n2\n"[..], &b""[..]));
+    }
+
+    #[test]
+    #[should_panic]
+    fn array_buffer_split_at_past_written_region_panics() {
+        let ab: ArrayBuffer<u8, 16> = ArrayBuffer::new();
+        ab.split_at(1);
+    }
+
+    #[test]
+    fn array_buffer_try_push_buf_reports_capacity() {
+        let mut ab: ArrayBuffer<u8, 8> = ArrayBuffer::new();
+        ab.try_push_buf(b"Pop\n").unwrap();
+
+        match ab.try_push_buf(b"Crackle\n") {
+            Err(CrackleError::Capacity { needed, available }) => {
+                assert_eq!((needed, available), (8, 4));
+            }
+            other => panic!("expected a capacity error, got {:?}", other),
+        }
+        // Nothing was written by the failed push.
+        assert_eq!(&ab[..], b"Pop\n");
+    }
+
+    #[test]
+    fn array_buffer_write_all_lossy_reports_dropped_bytes() {
+        let mut ab: ArrayBuffer<u8, 8> = ArrayBuffer::new();
+        assert_eq!(ab.write_all_lossy(b"Pop\n"), 0);
+        assert_eq!(ab.write_all_lossy(b"Crackle\n"), 4);
+        assert_eq!(ab, *b"Pop\nCrac");
+        assert_eq!(ab.write_all_lossy(b"x"), 1);
+        assert_eq!(ab.pos, 8);
+    }
+
+    #[test]
+    fn array_buffer_write_if_fits_is_all_or_nothing() {
+        let mut ab: ArrayBuffer<u8, 8> = ArrayBuffer::new();
+        assert!(ab.write_if_fits(b"Pop\n"));
+        assert!(!ab.write_if_fits(b"Crackle\n"));
+        assert!(ab.eq_str("Pop\n"));
+        assert!(ab.write_if_fits(b"Pop\n"));
+        assert!(ab.eq_str("Pop\nPop\n"));
+    }
+
+    #[test]
+    fn array_buffer_pad_to_aligns_columns() {
+        let mut ab: ArrayBuffer<u8, 40> = ArrayBuffer::new();
+        for word in [&b"1"[..], b"Crackle", b"CracklePop"] {
+            let start = ab.pos;
+            ab.push_buf(word);
+            ab.pad_to(start + 10, b' ');
+            ab.push(b'|');
+        }
+        assert_eq!(&ab[..], b"1         |Crackle   |CracklePop|");
+
+        // Already at or past `len` is a no-op.
+        ab.pad_to(4, b'.');
+        assert_eq!(ab.pos, 33);
+
+        ab.try_pad_to(36, b'-').unwrap();
+        assert!(ab[..].ends_with(b"|---"));
+        assert!(matches!(
+            ab.try_pad_to(41, b'-'),
+            Err(CrackleError::Capacity {
+                needed: 5,
+                available: 4
+            })
+        ));
+        assert_eq!(ab.pos, 36);
+    }
+
+    #[test]
+    fn array_buffer_fill_with_pattern_wraps_at_the_end() {
+        let mut ab: ArrayBuffer<u8, 9> = ArrayBuffer::new();
+        ab.push_buf(b"1\n");
+        ab.fill_with_pattern(b"Pop\n");
+        assert_eq!(&ab[..], b"1\nPop\nPop");
+
+        // Filling a full buffer, or with an empty pattern, is a no-op.
+        ab.fill_with_pattern(b"Crackle");
+        assert_eq!(&ab[..], b"1\nPop\nPop");
+        let mut ab: ArrayBuffer<u8, 4> = ArrayBuffer::new();
+        ab.fill_with_pattern(b"");
+        assert_eq!(ab.pos, 0);
+    }
+
+    #[test]
+    fn array_buffer_new_boxed_skips_the_stack() {
+        // 16 MiB, far more than a test thread's stack.
+        let mut ab: Box<ArrayBuffer<u8, 0x100_0000>> = ArrayBuffer::new_boxed();
+        assert_eq!(ab.pos, 0);
+        assert!(ab.buf.iter().all(|&b| b == 0));
+        crate::engine::crackle_pop_into(1..=100, &mut *ab, true).unwrap();
+        assert_eq!(ab.pos, super::exact_bytes(1, 100));
+    }
+
+    #[test]
+    fn array_buffer_write_separated() {
+        let mut ab: ArrayBuffer<u8, 64> = ArrayBuffer::new();
+        ab.write_separated(&[], b',');
+        assert_eq!(ab.pos, 0);
+        ab.write_separated(&[7], b',');
+        assert_eq!(&ab[..], b"7");
+
+        let mut ab: ArrayBuffer<u8, 64> = ArrayBuffer::new();
+        ab.write_separated(&[1, 2, 4, 7, 8, u64::MAX], b',');
+        assert_eq!(&ab[..], b"1,2,4,7,8,18446744073709551615");
+    }
+
+    #[test]
+    fn array_buffer_shrink() {
+        let mut ab: ArrayBuffer<u8, ARRAY_BUFFER_SIZE> = ArrayBuffer::new();
+        super::crackle_pop_fastest_arraybuf(&mut ab);
+        let expected = ab[..].to_vec();
+
+        let ab = match ab.shrink::<{ super::exact_bytes(1, 100) - 1 }>() {
+            Ok(_) => panic!("shrank into a buffer that's too small"),
+            Err(ab) => ab,
+        };
+        assert_eq!(&ab[..], &expected[..]);
+
+        let mut shrunk: crackle_buffer!(1, 100) = ab.shrink().unwrap();
+        assert_eq!(&shrunk[..], &expected[..]);
+        assert!(shrunk.try_push_buf(b"\n").is_err());
+    }
+
+    #[test]
+    fn array_buffer_nth_line() {
+        let mut ab: ArrayBuffer<u8, ARRAY_BUFFER_SIZE> = ArrayBuffer::new();
+        assert_eq!(ab.nth_line(0), None);
+
+        super::crackle_pop_fastest_arraybuf(&mut ab);
+        assert_eq!(ab.nth_line(0), Some(&b"1"[..]));
+        assert_eq!(ab.nth_line(2), Some(&b"Crackle"[..]));
+        assert_eq!(ab.nth_line(14), Some(&b"CracklePop"[..]));
+        assert_eq!(ab.nth_line(99), Some(&b"Pop"[..]));
+        assert_eq!(ab.nth_line(100), None);
+
+        // Without a trailing newline the last line still counts.
+        let mut ab: ArrayBuffer<u8, 16> = ArrayBuffer::new();
+        ab.push_buf(b"1\n\n3");
+        assert_eq!(ab.nth_line(1), Some(&b""[..]));
+        assert_eq!(ab.nth_line(2), Some(&b"3"[..]));
+        assert_eq!(ab.nth_line(3), None);
+    }
+
+    #[test]
+    fn array_buffer_eq_ignores_unwritten_tail() {
+        let mut ab: ArrayBuffer<u8, 16> = ArrayBuffer::new();
+        assert!(ab.eq_str(""));
+        ab.push_buf_line(b"Crackle");
+        assert!(ab.eq_str("Crackle\n"));
+        assert!(ab.eq_bytes(b"Crackle\n"));
+        assert_eq!(ab.as_slice(), &ab[..]);
+        assert!(!ab.eq_str("Crackle"));
+        assert!(!ab.eq_bytes(&[b'C'; 16]));
+    }
+
+    #[test]
+    fn array_buffer_write_escaped() {
+        let mut ab: ArrayBuffer<u8, 32> = ArrayBuffer::new();
+        ab.push_buf(b"Crackle\\Pop\n\r\x00\x7f\xffend ~");
+        let mut out = Vec::new();
+        ab.write_escaped(&mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            r"Crackle\\Pop\x0a\x0d\x00\x7f\xffend ~"
+        );
+    }
+
+    #[test]
+    fn array_buffer_normalize_newlines() {
+        let mut ab: ArrayBuffer<u8, 32> = ArrayBuffer::new();
+        ab.push_buf(b"1\r\n2\rCrackle\n\r\r\nPop\r");
+        ab.normalize_newlines();
+        assert!(ab.eq_str("1\n2\nCrackle\n\n\nPop\n"));
+
+        ab.normalize_newlines();
+        assert!(ab.eq_str("1\n2\nCrackle\n\n\nPop\n"));
+    }
+
+    #[test]
+    fn array_buffer_hex_dump() {
+        let mut ab: ArrayBuffer<u8, ARRAY_BUFFER_SIZE> = ArrayBuffer::new();
+        let mut dump = Vec::new();
+        ab.hex_dump(&mut dump).unwrap();
+        assert!(dump.is_empty());
+
+        crate::engine::crackle_pop_into(1..=6, &mut ab, true).unwrap();
+        ab.hex_dump(&mut dump).unwrap();
+        assert_eq!(
+            String::from_utf8(dump).unwrap(),
+            "00000000  31 0a 32 0a 43 72 61 63  6b 6c 65 0a 34 0a 50 6f  |1.2.Crackle.4.Po|\n\
+             00000010  70 0a 43 72 61 63 6b 6c  65 0a                    |p.Crackle.|\n"
+        );
+    }
+
+    #[test]
+    fn array_buffer_from_reader_stops_at_capacity() {
+        let mut reader = crate::engine::CracklePopIterator::new(1..=5);
+        let ab: ArrayBuffer<u8, 64> = ArrayBuffer::from_reader(&mut reader).unwrap();
+        assert!(ab.eq_str("1\n2\nCrackle\n4\nPop\n"));
+
+        let mut reader = crate::engine::CracklePopIterator::new(1..=5);
+        let ab: ArrayBuffer<u8, 8> = ArrayBuffer::from_reader(&mut reader).unwrap();
+        assert!(ab.eq_str("1\n2\nCrac"));
+        // The rest is still there for the next read.
+        let ab: ArrayBuffer<u8, 64> = ArrayBuffer::from_reader(&mut reader).unwrap();
+        assert!(ab.eq_str("kle\n4\nPop\n"));
+
+        /// Gets interrupted before every chunk it hands out.
+        struct Interrupting<'a>(&'a [u8], bool);
+        impl io::Read for Interrupting<'_> {
+            fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+                self.1 = !self.1;
+                if self.1 {
+                    return Err(io::ErrorKind::Interrupted.into());
+                }
+                let len = buf.len().min(2);
+                self.0.read(&mut buf[..len])
+            }
+        }
+        let mut reader = Interrupting(b"Crackle", false);
+        let ab: ArrayBuffer<u8, 64> = ArrayBuffer::from_reader(&mut reader).unwrap();
+        assert!(ab.eq_str("Crackle"));
+    }
+
+    #[test]
+    fn array_buffer_capacity_introspection() {
+        let mut ab: ArrayBuffer<u8, 16> = ArrayBuffer::new();
+        assert!(ab.is_empty());
+        assert_eq!((ab.len(), ab.remaining()), (0, 16));
+
+        ab.push_buf_line(b"CracklePop");
+        assert!(!ab.is_empty());
+        assert_eq!((ab.len(), ab.remaining()), (11, 5));
+        assert!(ab.has_room_for(5));
+        assert!(!ab.has_room_for(6));
+        assert!(!ab.has_room_for(usize::MAX));
+    }
+
+    #[test]
+    fn array_buffer_written_and_spare_partition_the_buffer() {
+        let mut ab = ArrayBuffer::with_backing([9u8; 8]);
+        assert_eq!(ab.written(), b"");
+        assert_eq!(ab.spare(), &[9; 8]);
+
+        ab.push_buf(b"Pop");
+        assert_eq!(ab.written(), b"Pop");
+        assert_eq!(ab.spare(), &[9; 5]);
+        assert_eq!([ab.written(), ab.spare()].concat(), &ab.backing()[..]);
+    }
+
+    #[test]
+    fn array_buffer_checked_push() {
+        let mut ab: ArrayBuffer<u8, 2> = ArrayBuffer::new();
+        assert_eq!(ab.checked_push(b'1'), Some(1));
+        assert_eq!(ab.checked_push(b'\n'), Some(2));
+        assert_eq!(ab.checked_push(b'2'), None);
+        assert!(ab.eq_str("1\n"));
+    }
+
+    #[test]
+    fn array_buffer_debug_shows_written_bytes() {
+        let mut ab: ArrayBuffer<u8, ARRAY_BUFFER_SIZE> = ArrayBuffer::new();
+        ab.push_buf_line(b"\"Pop\"");
+        ab.push(0xff);
+        assert_eq!(
+            format!("{:?}", ab),
+            r#"ArrayBuffer { pos: 7, capacity: 2048, written: b"\"Pop\"\n\xff" }"#
+        );
+        assert_eq!(
+            format!("{:?}", ab.scope()),
+            r#"BufferScope(ArrayBuffer { pos: 7, capacity: 2048, written: b"\"Pop\"\n\xff" })"#
+        );
+
+        let mut units: ArrayBuffer<u16, 8> = ArrayBuffer::new();
+        units.push_str("Po");
+        assert_eq!(
+            format!("{:?}", units),
+            "ArrayBuffer { pos: 2, capacity: 8, written: [80, 111] }"
+        );
+        let expected = format!("BufferScope({:?})", units);
+        assert_eq!(format!("{:?}", units.scope()), expected);
+    }
+
+    #[test]
+    fn array_buffer_get_and_set_stay_within_written_region() {
+        let mut ab: ArrayBuffer<u8, 8> = ArrayBuffer::new();
+        ab.push_buf(b"Pip\n");
+        assert_eq!(ab.set(1, b'o'), Some(b'i'));
+        assert!(ab.eq_str("Pop\n"));
+        assert_eq!(ab.get(1), Some(&b'o'));
+
+        assert_eq!(ab.set(4, b'!'), None);
+        assert_eq!(ab.get(4), None);
+        assert_eq!(ab.len(), 4);
+        assert_eq!(ab.spare(), &[0; 4]);
+    }
+
+    #[cfg(feature = "smallvec")]
+    #[test]
+    fn array_buffer_smallvec_round_trip() {
+        use smallvec::SmallVec;
+
+        let vec: SmallVec<[u8; 4]> = SmallVec::from_slice(b"Crackle\n");
+        let ab: ArrayBuffer<u8, 16> = ArrayBuffer::from_smallvec(vec).unwrap();
+        assert!(ab.eq_str("Crackle\n"));
+
+        let vec: SmallVec<[u8; 16]> = ab.into_smallvec();
+        assert!(!vec.spilled());
+        assert_eq!(&vec[..], b"Crackle\n");
+
+        assert!(matches!(
+            ArrayBuffer::<u8, 4>::from_smallvec(vec),
+            Err(CrackleError::Capacity {
+                needed: 8,
+                available: 4
+            })
+        ));
+    }
+
+    #[test]
+    fn array_buffer_guarded_writer_errors_when_full() {
+        let mut ab: ArrayBuffer<u8, 10> = ArrayBuffer::new();
+        let mut writer = ab.guarded_writer();
+        writer.write_all(b"Crackle\n").unwrap();
+        let err = writeln!(writer, "{}", 1234).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::WriteZero);
+        assert_eq!(writer.write(b"").unwrap(), 0);
+        assert!(ab.eq_str("Crackle\n12"));
+    }
+
+    #[test]
+    fn array_buffer_rotate_and_truncate_keeps_the_tail() {
+        let mut ab: ArrayBuffer<u8, 16> = ArrayBuffer::new();
+        ab.push_buf(b"Crackle\nPop\n");
+        // Overlapping: the last 8 bytes start before the midpoint.
+        ab.rotate_and_truncate(8);
+        assert!(ab.eq_str("kle\nPop\n"));
+
+        ab.rotate_and_truncate(4);
+        assert!(ab.eq_str("Pop\n"));
+        ab.rotate_and_truncate(10);
+        assert!(ab.eq_str("Pop\n"));
+        ab.rotate_and_truncate(0);
+        assert!(ab.is_empty());
+    }
+
+    #[test]
+    fn array_buffer_dedup_matches_vec() {
+        for input in [
+            &b""[..],
+            b"1\n\n\n2\n",
+            b"aaaa",
+            b"Crackle\n\nPop\n\n",
+            b"abc",
+        ] {
+            let mut ab: ArrayBuffer<u8, 16> = ArrayBuffer::new();
+            ab.push_buf(input);
+            ab.dedup();
+
+            let mut vec = input.to_vec();
+            vec.dedup();
+            assert!(ab.eq_bytes(&vec), "{:?}", input);
+        }
+    }
+
+    #[test]
+    fn array_buffer_find_respects_written_region() {
+        let mut ab: ArrayBuffer<u8, ARRAY_BUFFER_SIZE> = ArrayBuffer::new();
+        super::crackle_pop_fastest_arraybuf(&mut ab);
+
+        assert_eq!(ab.find(b"1\n"), Some(0));
+        assert_eq!(ab.find(b"Crackle"), Some(4));
+        assert_eq!(ab.find(b"\n"), Some(1));
+        assert!(ab.contains(b"\nCracklePop\n"));
+        assert!(!ab.contains(b"101"));
+        // The zeroed tail is never searched.
+        assert!(!ab.contains(&[0]));
+        assert_eq!(ab.find(b""), Some(0));
+    }
+
+    #[test]
+    fn array_buffer_spare_capacity_and_advance() {
+        let mut ab: ArrayBuffer<u8, 8> = ArrayBuffer::new();
+        ab.push_buf(b"ab");
+
+        let spare = ab.spare_capacity_mut();
+        assert_eq!(spare.len(), 6);
+        spare[..3].copy_from_slice(b"cde");
+        unsafe { ab.advance(3) };
+
+        assert_eq!(ab.pos, 5);
+        assert_eq!(ab, *b"abcde");
+    }
+
+    #[test]
+    fn array_buffer_write_with_commits_what_the_closure_wrote() {
+        let mut ab: ArrayBuffer<u8, 16> = ArrayBuffer::new();
+        ab.push_buf(b"n=");
+        ab.write_with(10, |mut spare| {
+            let len = spare.len();
+            write!(spare, "{}", 12345).unwrap();
+            len - spare.len()
+        });
+        ab.write_with(0, |_| 0);
+
+        assert_eq!(ab, *b"n=12345");
+        assert_eq!(ab.remaining(), 9);
+    }
+
+    #[test]
+    #[should_panic]
+    fn array_buffer_write_with_rejects_overclaiming() {
+        let mut ab: ArrayBuffer<u8, 16> = ArrayBuffer::new();
+        ab.write_with(4, |_| 5);
+    }
+
+    #[test]
+    fn array_buffer_drain_removes_a_line() {
+        let mut ab: ArrayBuffer<u8, 32> = ArrayBuffer::new();
+        ab.push_buf_line(b"1");
+        ab.push_buf_line(b"2");
+        ab.push_buf_line(b"Crackle");
+
+        ab.drain(2..4);
+        assert_eq!(ab, *b"1\nCrackle\n");
+
+        ab.drain(0..0);
+        assert_eq!(ab, *b"1\nCrackle\n");
+    }
+
+    #[test]
+    #[should_panic]
+    fn array_buffer_drain_past_written_region_panics() {
+        let mut ab: ArrayBuffer<u8, 32> = ArrayBuffer::new();
+        ab.push_buf_line(b"1");
+        ab.drain(1..3);
+    }
+
+    #[test]
+    fn array_buffer_insert_shifts_the_tail() {
+        let mut ab: ArrayBuffer<u8, 32> = ArrayBuffer::new();
+        ab.push_buf_line(b"1");
+        ab.push_buf_line(b"2");
+
+        ab.insert(0, b"header\n");
+        assert_eq!(ab, *b"header\n1\n2\n");
+        ab.insert(ab.pos, b"3\n");
+        assert_eq!(ab, *b"header\n1\n2\n3\n");
+        ab.insert(9, b"Crackle\n");
+        assert_eq!(ab, *b"header\n1\nCrackle\n2\n3\n");
+    }
+
+    #[test]
+    #[should_panic]
+    fn array_buffer_insert_past_capacity_panics() {
+        let mut ab: ArrayBuffer<u8, 4> = ArrayBuffer::new();
+        ab.push_buf(b"abc");
+        ab.insert(1, b"xy");
+    }
+
+    #[test]
+    fn verify_utf8_once_rejects_invalid_bytes() {
+        let mut ab: ArrayBuffer<u8, 32> = ArrayBuffer::new();
+        ab.verify_utf8_once();
+        ab.push_buf(b"Crackle\n\xff");
+        let mut out = Vec::new();
+        let err = ab.write_all_to(&mut out).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(out.is_empty());
+        assert_eq!(ab.len(), 9);
+
+        ab.set(8, b'\n');
+        ab.write_all_to(&mut out).unwrap();
+        assert_eq!(out, b"Crackle\n\n");
+    }
+
+    #[test]
+    fn verify_utf8_once_only_checks_new_bytes() {
+        let mut ab: ArrayBuffer<u8, 32> = ArrayBuffer::new();
+        ab.verify_utf8_once();
+        ab.push_buf("Pop é".as_bytes());
+        ab.check_utf8().unwrap();
+        assert_eq!(ab.utf8_checked, Some(6));
+
+        ab.push_buf_line(b"!");
+        ab.check_utf8().unwrap();
+        assert_eq!(ab.utf8_checked, Some(8));
+
+        ab.drain(0..4);
+        assert_eq!(ab.utf8_checked, Some(0));
+        ab.write_all_to(&mut io::sink()).unwrap();
+        assert_eq!(ab.utf8_checked, Some(0));
+
+        // A split multibyte character is caught across calls.
+        ab.push_buf(&"é".as_bytes()[..1]);
+        assert!(ab.check_utf8().is_err());
+        assert_eq!(ab.utf8_checked, Some(0));
+    }
+
+    #[test]
+    fn verify_utf8_once_rechecks_edits_inside_a_char() {
+        fn checked(text: &str) -> ArrayBuffer<u8, 32> {
+            let mut ab = ArrayBuffer::new();
+            ab.verify_utf8_once();
+            ab.push_buf(text.as_bytes());
+            ab.check_utf8().unwrap();
+            ab
+        }
+        fn rejected(mut ab: ArrayBuffer<u8, 32>) {
+            let mut out = Vec::new();
+            let err = ab.write_all_to(&mut out).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+            assert!(out.is_empty());
+        }
+
+        // Each of these cuts into the two bytes of "é", 0xC3 0xA9.
+        let mut ab = checked("éx");
+        ab.drain(1..2);
+        assert_eq!(ab, [0xC3, b'x']);
+        rejected(ab);
+
+        let mut ab = checked("éx");
+        ab.insert(1, b"y");
+        rejected(ab);
+
+        let mut ab = checked("aéx");
+        ab.set(2, b'b');
+        rejected(ab);
+
+        // Setting a byte to what it already was changes nothing.
+        let mut ab = checked("aéx");
+        ab.set(2, 0xA9);
+        let mut out = Vec::new();
+        ab.write_all_to(&mut out).unwrap();
+        assert_eq!(out, "aéx".as_bytes());
+
+        // Three and four byte chars, edited at their last byte.
+        let mut ab = checked("€🎉");
+        ab.set(2, b'!');
+        rejected(ab);
+        let mut ab = checked("€🎉");
+        ab.set(6, b'!');
+        rejected(ab);
+    }
+
+    #[test]
+    fn utf16_buffer_writes_little_endian() {
+        let mut expected = Vec::new();
+        super::engine::crackle_pop_into(1..=300, &mut expected, true).unwrap();
+        let expected = String::from_utf8(expected).unwrap();
+
+        let mut ab: ArrayBuffer<u16, 2048> = ArrayBuffer::new();
+        for n in 1..=300 {
+            match (n % 3, n % 5) {
+                (0, 0) => ab.push_str("CracklePop"),
+                (0, _) => ab.push_str("Crackle"),
+                (_, 0) => ab.push_str("Pop"),
+                _ => ab.push_u64(n),
+            }
+            ab.push_units(&[u16::from(b'\n')]);
+        }
+
+        let mut out = Vec::new();
+        ab.write_all_to(&mut out).unwrap();
+        let expected: Vec<u8> = expected.encode_utf16().flat_map(u16::to_le_bytes).collect();
+        assert_eq!(out, expected);
+        assert!(ab.is_empty());
+
+        ab.push_str("é😀");
+        assert_eq!(ab.as_slice(), &[0xe9, 0xd83d, 0xde00]);
+    }
+
+    #[test]
+    fn write_base64_matches_rfc_4648_vectors() {
+        let vectors = [
+            ("", ""),
+            ("f", "Zg=="),
+            ("fo", "Zm8="),
+            ("foo", "Zm9v"),
+            ("foob", "Zm9vYg=="),
+            ("fooba", "Zm9vYmE="),
+            ("foobar", "Zm9vYmFy"),
+        ];
+        for (input, expected) in vectors {
+            let mut ab: ArrayBuffer<u8, 8> = ArrayBuffer::new();
+            ab.push_buf(input.as_bytes());
+            let mut out = Vec::new();
+            ab.write_base64(&mut out).unwrap();
+            assert_eq!(String::from_utf8(out).unwrap(), expected);
+        }
+
+        let mut out = Vec::new();
+        crackle_pop_into_new_arraybuf::<512>()
+            .write_base64(&mut out)
+            .unwrap();
+        assert_eq!(out.len(), 656);
+        assert!(out.starts_with(b"MQoyCkNyYWNrbGUKNApQb3AK"));
+        assert!(out.ends_with(b"Y2tsZQpQb3AK"));
+    }
+
+    #[test]
+    fn compact_clone_copies_only_the_written_region() {
+        let mut ab: ArrayBuffer<u8, ARRAY_BUFFER_SIZE> = ArrayBuffer::new();
+        ab.push_buf_line(b"CracklePop");
+
+        let clone: ArrayBuffer<u8, 11> = ab.compact_clone().unwrap();
+        assert!(clone.eq_str("CracklePop\n"));
+        assert!(ab.eq_str("CracklePop\n"));
+
+        assert!(matches!(
+            ab.compact_clone::<8>(),
+            Err(CrackleError::Capacity {
+                needed: 11,
+                available: 8
+            })
+        ));
+    }
+
+    #[test]
+    fn write_all_to_retries_when_interrupted() {
+        /// Interrupted on the first write, and with a `write_all` that doesn't
+        /// retry, unlike the default one.
+        #[derive(Default)]
+        struct InterruptedOnce(Vec<u8>, bool);
+        impl Write for InterruptedOnce {
+            fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+                if !self.1 {
+                    self.1 = true;
+                    return Err(io::ErrorKind::Interrupted.into());
+                }
+                self.0.write(buf)
+            }
+            fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
+                self.write(buf).map(drop)
+            }
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let mut ab: ArrayBuffer<u8, 16> = ArrayBuffer::new();
+        ab.push_buf_line(b"Crackle");
+        let mut sink = InterruptedOnce::default();
+        ab.write_all_to(&mut sink).unwrap();
+        assert_eq!(sink.0, b"Crackle\n");
+        assert!(ab.is_empty());
+
+        let mut ab: ArrayBuffer<u16, 16> = ArrayBuffer::new();
+        ab.push_str("Pop");
+        let mut sink = InterruptedOnce::default();
+        ab.write_all_to(&mut sink).unwrap();
+        assert_eq!(sink.0, b"P\0o\0p\0");
+    }
+
+    #[test]
+    fn write_all_to_deadline_resumes_where_it_stopped() {
+        use std::time::{Duration, Instant};
+
+        /// Takes 4 bytes per write, and 5ms to do it.
+        #[derive(Default)]
+        struct SlowSink(Vec<u8>);
+        impl Write for SlowSink {
+            fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+                std::thread::sleep(Duration::from_millis(5));
+                self.0.write(&buf[..buf.len().min(4)])
+            }
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let mut ab: ArrayBuffer<u8, ARRAY_BUFFER_SIZE> = ArrayBuffer::new();
+        super::crackle_pop_fastest_arraybuf(&mut ab);
+        let expected = ab.as_slice().to_vec();
+
+        let mut sink = SlowSink::default();
+        let deadline = Instant::now() + Duration::from_millis(12);
+        let err = ab.write_all_to_deadline(&mut sink, deadline).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::WouldBlock);
+        assert!(sink.0.len() < expected.len());
+        assert_eq!([&sink.0[..], ab.as_slice()].concat(), expected);
+
+        // An expired deadline writes nothing at all.
+        let len = ab.len();
+        assert!(ab.write_all_to_deadline(&mut sink, deadline).is_err());
+        assert_eq!(ab.len(), len);
+
+        let mut rest = Vec::new();
+        ab.write_all_to_deadline(&mut rest, Instant::now() + Duration::from_secs(60))
+            .unwrap();
+        sink.0.extend_from_slice(&rest);
+        assert_eq!(sink.0, expected);
+        assert!(ab.is_empty());
+    }
+
+    #[test]
+    fn scope_clears_the_buffer_on_drop() {
+        let mut ab: ArrayBuffer<u8, ARRAY_BUFFER_SIZE> = ArrayBuffer::new();
+        for _ in 0..3 {
+            let mut scope = ab.scope();
+            super::crackle_pop_fastest_arraybuf(&mut scope);
+            assert_eq!(scope.len(), super::exact_bytes(1, 100));
+            writeln!(scope, "{}", 101).unwrap();
+            assert!(scope.as_slice().ends_with(b"Pop\n101\n"));
+        }
+        assert_eq!(ab.len(), 0);
+    }
+
+    #[test]
+    fn raw_pointers_cover_the_backing_array() {
+        let mut ab: ArrayBuffer<u8, 16> = ArrayBuffer::new();
+        ab.push_buf(b"Crackle");
+        let written = unsafe { std::slice::from_raw_parts(ab.as_ptr(), ab.len()) };
+        assert_eq!(written, b"Crackle");
+
+        // Filling in the tail like a C API would, then committing it.
+        unsafe {
+            let ptr = ab.as_mut_ptr().add(ab.len());
+            std::ptr::copy_nonoverlapping(b"Pop".as_ptr(), ptr, 3);
+            ab.advance(3);
+        }
+        assert!(ab.eq_str("CracklePop"));
+    }
+
+    #[test]
+    fn to_cow_only_allocates_for_invalid_utf8() {
+        let mut ab: ArrayBuffer<u8, 16> = ArrayBuffer::new();
+        ab.push_buf_line(b"Pop");
+        assert!(matches!(ab.to_cow(), Cow::Borrowed("Pop\n")));
+
+        ab.push(0xff);
+        let cow = ab.to_cow();
+        assert!(matches!(cow, Cow::Owned(_)));
+        assert_eq!(cow, "Pop\n\u{fffd}");
+    }
+
+    #[test]
+    fn for_each_line_routes_lines() {
+        let ab = crackle_pop_into_new_arraybuf::<512>();
+        let (mut words, mut numbers) = (Vec::new(), 0);
+        ab.for_each_line(|line| {
+            if line[0].is_ascii_digit() {
+                numbers += 1;
+            } else {
+                words.push(line.to_vec());
+            }
+        });
+        assert_eq!(numbers, 53);
+        assert_eq!(words.len(), 47);
+        assert_eq!(words[..3], [&b"Crackle"[..], b"Pop", b"Crackle"]);
+
+        let mut lines = Vec::new();
+        let mut ab: ArrayBuffer<u8, 16> = ArrayBuffer::new();
+        ab.for_each_line(|line| lines.push(line.to_vec()));
+        assert!(lines.is_empty());
+        ab.push_buf(b"1\n\nPop");
+        ab.for_each_line(|line| lines.push(line.to_vec()));
+        assert_eq!(lines, [&b"1"[..], b"", b"Pop"]);
+    }
+
+    #[test]
+    fn saturating_push_buf_drops_the_excess() {
+        let mut ab: ArrayBuffer<u8, 8> = ArrayBuffer::new();
+        ab.saturating_push_buf(b"Crackle");
+        ab.saturating_push_buf(b"Pop");
+        assert!(ab.eq_str("CrackleP"));
+        ab.saturating_push_buf(b"Pop");
+        assert!(ab.eq_str("CrackleP"));
+    }
+
+    #[test]
+    fn new_zeroed_works_in_const_contexts() {
+        const EMPTY: ArrayBuffer<u8, ARRAY_BUFFER_SIZE> = ArrayBuffer::new_zeroed();
+        let mut ab = EMPTY;
+        assert!(ab.is_empty());
+        assert_eq!(
+            ab.backing(),
+            ArrayBuffer::<u8, ARRAY_BUFFER_SIZE>::new().backing()
+        );
+        super::crackle_pop_fastest_arraybuf(&mut ab);
+        assert_eq!(ab.len(), super::exact_bytes(1, 100));
+    }
+
+    /// With one byte left, push_line's value fits but its newline doesn't.
+    #[test]
+    #[should_panic]
+    fn push_line_panics_one_byte_short() {
+        let mut ab: ArrayBuffer<u8, 3> = ArrayBuffer::new();
+        ab.push_line(b'1');
+        ab.push_line(b'2');
+    }
+
+    #[test]
+    #[should_panic]
+    fn push_buf_line_panics_past_capacity() {
+        let mut ab: ArrayBuffer<u8, 10> = ArrayBuffer::new();
+        ab.push_buf_line(b"CracklePop");
+    }
+
+    /// The same overruns through the checked methods, which report them
+    /// instead and leave the buffer untouched.
+    #[test]
+    fn checked_writes_report_overflow() {
+        let mut ab: ArrayBuffer<u8, 3> = ArrayBuffer::new();
+        ab.push_line(b'1');
+        assert!(matches!(
+            ab.try_push_buf(b"2\n"),
+            Err(CrackleError::Capacity {
+                needed: 2,
+                available: 1
+            })
+        ));
+        assert!(!ab.write_if_fits(b"2\n"));
+        assert!(ab.try_pad_to(4, b' ').is_err());
+        assert_eq!(ab.checked_push(b'2'), Some(3));
+        assert_eq!(ab.checked_push(b'\n'), None);
+        assert!(ab.eq_str("1\n2"));
+    }
+
+    #[test]
+    fn try_push_fixed_hands_back_what_doesnt_fit() {
+        let mut ab: ArrayBuffer<u8, 5> = ArrayBuffer::new();
+        assert_eq!(ab.try_push_fixed(*b"Pop"), Ok(()));
+        let rejected = ab.try_push_fixed(*b"42\n").unwrap_err();
+        assert_eq!(&rejected, b"42\n");
+        assert!(ab.eq_str("Pop"));
+
+        let mut out = Vec::new();
+        ab.write_all_to(&mut out).unwrap();
+        assert_eq!(ab.try_push_fixed(rejected), Ok(()));
+        assert!(ab.eq_str("42\n"));
+        assert_eq!(ab.try_push_fixed([]), Ok(()));
+    }
+
+    #[test]
+    fn array_buffer_equals_its_written_region() {
+        let mut ab: ArrayBuffer<u8, 16> = ArrayBuffer::new();
+        ab.push_buf_line(b"Crackle");
+        assert_eq!(ab, *b"Crackle\n");
+        assert_eq!(ab, b"Crackle\n"[..]);
+        assert_eq!(ab, *"Crackle\n");
+        assert_eq!(ab, "Crackle\n");
+        // Not the whole backing array, nor a prefix.
+        assert_ne!(ab, [b"Crackle\n".as_slice(), &[0; 8]].concat()[..]);
+        assert_ne!(ab, "Crackle");
+    }
+
+    #[test]
+    fn write_all_to_flushes_crackle_pop_into_sink() {
+        let mut buf: ArrayBuffer<u8, ARRAY_BUFFER_SIZE> = ArrayBuffer::new();
+        super::crackle_pop_fastest_arraybuf(&mut buf);
+
+        let mut sink = MockSink::default();
+        buf.write_all_to(&mut sink).unwrap();
+        assert_eq!(buf.pos, 0);
+
+        let mut expected = Vec::new();
+        crate::engine::crackle_pop_into(1..=100, &mut expected, true).unwrap();
+        assert_eq!(sink.written, expected);
+        assert!(sink.written.starts_with(b"1\n2\nCrackle\n4\nPop\n"));
+        assert!(sink.written.ends_with(b"98\nCrackle\nPop\n"));
+
+        // A second flush of the now empty buffer writes nothing more.
+        buf.write_all_to(&mut sink).unwrap();
+        assert_eq!(sink.written, expected);
+    }
+
+    #[test]
+    fn write_all_to_vec_appends_and_empties() {
+        let mut ab: ArrayBuffer<u8, ARRAY_BUFFER_SIZE> = ArrayBuffer::new();
+        let mut out = b"head\n".to_vec();
+        super::crackle_pop_fastest_arraybuf(&mut ab);
+        let expected = [b"head\n", ab.as_slice()].concat();
+        ab.write_all_to_vec(&mut out);
+        assert_eq!(out, expected);
+        assert!(ab.is_empty());
+    }
+
+    #[test]
+    fn exact_bytes_matches_written_output() {
+        let mut vec = Vec::new();
+        super::crackle_pop_ext_vec_minimal_vars(&mut vec);
+        assert_eq!(vec.len(), super::exact_bytes(1, 100));
+
+        // A buffer sized by the macro fits the output with no room to spare.
+        let mut buf: crackle_buffer!(1, 100) = ArrayBuffer::new();
+        buf.write_all(&vec).unwrap();
+        assert_eq!(buf.pos, buf.buf.len());
+    }
+
+    /// CracklePop over `start..=end` the slow way, one number at a time, with
+    /// none of the crate's own classifying or encoding.
+    fn reference_output(start: u64, end: u64) -> String {
+        (start..=end)
+            .map(|n| match (n % 3 == 0, n % 5 == 0) {
+                (true, true) => "CracklePop\n".to_string(),
+                (true, false) => "Crackle\n".to_string(),
+                (false, true) => "Pop\n".to_string(),
+                (false, false) => format!("{}\n", n),
+            })
+            .collect()
+    }
+
+    /// `exact_bytes` the slow way.
+    fn brute_force(start: u64, end: u64) -> usize {
+        reference_output(start, end).len()
+    }
+
+    #[test]
+    fn exact_bytes_agrees_with_brute_force() {
+        for &(start, end) in &[
+            (0, 0),
+            (1, 1),
+            (1, 15),
+            (7, 7),
+            (9, 10),
+            (95, 1005),
+            (0, 12345),
+        ] {
+            assert_eq!(super::exact_bytes(start, end), brute_force(start, end));
+        }
+        assert_eq!(super::exact_bytes(10, 9), 0);
+    }
+
+    #[test]
+    fn checked_exact_bytes_refuses_to_overflow() {
+        assert_eq!(
+            super::checked_exact_bytes(1, 100),
+            Some(super::exact_bytes(1, 100))
+        );
+        assert_eq!(super::checked_exact_bytes(1, u64::MAX), None);
+        assert_eq!(super::checked_exact_bytes(0, u64::MAX), None);
+        // Right at the top, where the last digit band ends at u64::MAX.
+        assert_eq!(
+            super::checked_exact_bytes(u64::MAX - 1, u64::MAX),
+            Some(brute_force(u64::MAX - 1, u64::MAX))
+        );
+    }
+
+    #[test]
+    #[should_panic]
+    fn exact_bytes_panics_instead_of_wrapping() {
+        super::exact_bytes(1, u64::MAX);
+    }
+
+    /// Any buffer with at least `exact_bytes` of room left fits the engine's
+    /// output. A buffer's size has to be a constant, so the size is picked
+    /// from `SIZES`, at least the smallest one that fits, and the room left
+    /// is then varied by padding the buffer first.
+    #[cfg(feature = "proptest")]
+    mod exact_bytes_props {
+        use super::*;
+        use proptest::prelude::*;
+
+        const SIZES: [usize; 5] = [256, 512, 1024, 4096, 8192];
+
+        fn start() -> impl Strategy<Value = u64> {
+            prop_oneof![0..1_000u64, 0..u64::MAX / 2, u64::MAX - 1_000..=u64::MAX]
+        }
+
+        /// Runs the engine into an `N` byte buffer left with `room` bytes.
+        fn check<const N: usize>(start: u64, end: u64, room: usize) -> Result<(), TestCaseError> {
+            let padding = N - room;
+            let mut ab: ArrayBuffer<u8, N> = ArrayBuffer::new();
+            ab.pad_to(padding, b'.');
+            crate::engine::crackle_pop_into(start..=end, &mut ab, true).unwrap();
+
+            let expected = super::reference_output(start, end);
+            prop_assert_eq!(&ab[padding..], expected.as_bytes());
+            prop_assert_eq!(ab.remaining(), room - expected.len());
+            Ok(())
+        }
+
+        proptest! {
+            #[test]
+            fn room_for_exact_bytes_never_panics(
+                start in start(),
+                len in 0..150u64,
+                slack in 0..64usize,
+                bigger in 0..SIZES.len(),
+            ) {
+                let end = start.saturating_add(len);
+                let room = super::super::exact_bytes(start, end) + slack;
+                let fits = SIZES.iter().position(|&size| size >= room).unwrap();
+                match (fits + bigger).min(SIZES.len() - 1) {
+                    0 => check::<256>(start, end, room)?,
+                    1 => check::<512>(start, end, room)?,
+                    2 => check::<1024>(start, end, room)?,
+                    3 => check::<4096>(start, end, room)?,
+                    _ => check::<8192>(start, end, room)?,
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn crackle_pop_max_bytes_bounds_custom_rules() {
+        const CLASSIC: &[(u64, &str)] = &[(3, "Crackle"), (5, "Pop")];
+        assert_eq!(super::longest_word(CLASSIC), 7);
+        assert_eq!(super::longest_word(&[]), 0);
+        assert!(super::crackle_pop_max_bytes(1, 100, CLASSIC) >= super::exact_bytes(1, 100));
+
+        // Usable as a buffer size, including with words far longer than
+        // "CracklePop".
+        const LONG: &[(u64, &str)] =
+            &[(2, "Supercalifragilistic"), (3, "Expialidocious"), (7, "!")];
+        let mut ab: ArrayBuffer<u8, { super::crackle_pop_max_bytes(1, 50, LONG) }> =
+            ArrayBuffer::new();
+        let config = crate::engine::Config {
+            range: 1..=50,
+            rules: LONG.to_vec(),
+            ..Default::default()
+        };
+        config.run_into(&mut ab).unwrap();
+        assert!(ab.contains(b"\nSupercalifragilisticExpialidocious!\n"));
+
+        for &(start, end) in &[
+            (1, 1),
+            (0, 0),
+            (9, 10),
+            (99_990, 100_010),
+            (u64::MAX - 3, u64::MAX),
+        ] {
+            let config = crate::engine::Config {
+                range: start..=end,
+                rules: LONG.to_vec(),
+                ..Default::default()
+            };
+            let mut out = Vec::new();
+            config.run_into(&mut out).unwrap();
+            assert!(out.len() <= super::crackle_pop_max_bytes(start, end, LONG));
+        }
+        assert_eq!(super::crackle_pop_max_bytes(5, 4, LONG), 0);
+    }
+
+    #[test]
+    fn write_u8_as_utf8_works() {
+        let mut buf = Vec::new();
+
+        super::write_u8_as_utf8(6, &mut buf);
+        assert_eq!(&buf, "6".as_bytes());
+
+        buf.clear();
+
+        super::write_u8_as_utf8(81, &mut buf);
+        assert_eq!(&buf, "81".as_bytes());
+
+        buf.clear();
+
+        super::write_u8_as_utf8(240, &mut buf);
+        assert_eq!(&buf, "240".as_bytes());
+
+        // 0 is a single digit like any other, not an empty string.
+        buf.clear();
+        super::write_u8_as_utf8(0, &mut buf);
+        super::write_u64_as_utf8(0, &mut buf).unwrap();
+        assert_eq!(&buf, "00".as_bytes());
+
+        let mut ab: ArrayBuffer<u8, 8> = ArrayBuffer::new();
+        ab.write_u8_as_utf8(0);
+        ab.write_u8_as_utf8_with_newline(0);
+        assert!(ab.eq_str("00\n"));
+    }
+
+    /// Locks in the three digit path, which used to be the slow `format!`
+    /// fallback everywhere, before `U8_DECIMAL` replaced it.
+    #[test]
+    fn write_u8_as_utf8_three_digits() {
+        let mut buf = Vec::new();
+        let mut ab: ArrayBuffer<u8, 4> = ArrayBuffer::new();
+        for n in 100..=u8::MAX {
+            buf.clear();
+            super::write_u8_as_utf8(n, &mut buf);
+            assert_eq!(buf, n.to_string().as_bytes());
+
+            ab.pos = 0;
+            ab.write_u8_as_utf8(n);
+            assert_eq!(&ab[..], n.to_string().as_bytes());
+
+            ab.pos = 0;
+            ab.write_u8_as_utf8_with_newline(n);
+            assert_eq!(&ab[..], format!("{}\n", n).as_bytes());
+        }
+    }
+
+    /// Every u8, through both the `U8_DECIMAL` lookup and the branching
+    /// encoder it replaced.
+    #[test]
+    fn u8_decimal_table_matches_to_string() {
+        for n in 0..=u8::MAX {
+            let mut buf = Vec::new();
+            super::write_u8_as_utf8(n, &mut buf);
+            assert_eq!(buf, n.to_string().as_bytes());
+            buf.clear();
+            super::write_u8_as_utf8_branchy(n, &mut buf);
+            assert_eq!(buf, n.to_string().as_bytes());
+        }
+    }
+
+    #[test]
+    fn write_u64_as_utf8_works() {
+        let mut buf = Vec::new();
+        for &n in &[0, 7, 42, 240, 1_000_000, u64::MAX] {
+            super::write_u64_as_utf8(n, &mut buf).unwrap();
+            assert_eq!(String::from_utf8_lossy(&buf), n.to_string());
+            buf.clear();
+        }
+    }
+
+    #[test]
+    fn write_uint_radix_works() {
+        fn radix(x: u64, radix: u32) -> String {
+            let mut buf = Vec::new();
+            super::write_uint_radix(x, radix, &mut buf).unwrap();
+            String::from_utf8(buf).unwrap()
+        }
+
+        assert_eq!(radix(0, 2), "0");
+        assert_eq!(radix(0, 36), "0");
+        assert_eq!(radix(5, 2), "101");
+        assert_eq!(radix(255, 16), "ff");
+        assert_eq!(radix(35, 36), "z");
+        assert_eq!(radix(1234, 10), "1234");
+        assert_eq!(radix(u64::MAX, 2), "1".repeat(64));
+        assert_eq!(radix(u64::MAX, 16), format!("{:x}", u64::MAX));
+
+        let mut buf = Vec::new();
+        for bad in [0, 1, 37] {
+            let err = super::write_uint_radix(10, bad, &mut buf).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        }
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn write_uint_with_digits_handles_multibyte_glyphs() {
+        const FULLWIDTH: [&[u8]; 10] = [
+            "０".as_bytes(),
+            "１".as_bytes(),
+            "２".as_bytes(),
+            "３".as_bytes(),
+            "４".as_bytes(),
+            "５".as_bytes(),
+            "６".as_bytes(),
+            "７".as_bytes(),
+            "８".as_bytes(),
+            "９".as_bytes(),
+        ];
+        fn fullwidth(x: u64) -> String {
+            let mut buf = Vec::new();
+            super::write_uint_with_digits(x, &FULLWIDTH, &mut buf).unwrap();
+            String::from_utf8(buf).unwrap()
+        }
+
+        assert_eq!(fullwidth(0), "０");
+        assert_eq!(fullwidth(1907), "１９０７");
+        assert_eq!(fullwidth(u64::MAX).chars().count(), 20);
+
+        let ascii: [&[u8]; 10] = [b"0", b"1", b"2", b"3", b"4", b"5", b"6", b"7", b"8", b"9"];
+        let mut buf = Vec::new();
+        super::write_uint_with_digits(u64::MAX, &ascii, &mut buf).unwrap();
+        assert_eq!(buf, u64::MAX.to_string().as_bytes());
+    }
+
+    #[test]
+    fn format_u64_into_matches_to_string() {
+        let mut buf = [0u8; 20];
+        let mut n = 1;
+        while n < u64::MAX / 10 {
+            for &x in &[n - 1, n, n + 7] {
+                assert_eq!(super::format_u64_into(x, &mut buf), x.to_string());
+            }
+            n *= 10;
+        }
+        assert_eq!(
+            super::format_u64_into(u64::MAX, &mut buf),
+            u64::MAX.to_string()
+        );
+
+        // Exactly as many bytes as there are digits is enough.
+        assert_eq!(super::format_u64_into(999, &mut [0u8; 3]), "999");
+    }
+
+    #[test]
+    #[should_panic]
+    fn format_u64_into_panics_when_too_small() {
+        super::format_u64_into(1000, &mut [0u8; 3]);
+    }
+
+    #[test]
+    fn write_uint_capped_matches_to_string() {
+        assert_eq!(<u8 as super::MaxDigits>::MAX_DIGITS, 3);
+        assert_eq!(<u64 as super::MaxDigits>::MAX_DIGITS, 20);
+
+        let mut scratch = [0u8; 3];
+        for n in 0..=u8::MAX {
+            assert_eq!(
+                super::write_uint_capped(n, &mut scratch),
+                n.to_string().as_bytes()
+            );
+        }
+
+        let mut scratch = [0u8; 20];
+        for &n in &[0, 9, 10, 65_535, u64::from(u32::MAX) + 1, u64::MAX] {
+            assert_eq!(
+                super::write_uint_capped(n, &mut scratch),
+                n.to_string().as_bytes()
+            );
+        }
+        // A bigger scratch than needed is fine too.
+        assert_eq!(super::write_uint_capped(7u16, &mut [0u8; 8]), b"7");
+    }
+
+    #[test]
+    fn display_u64_matches_std() {
+        use super::DisplayU64;
+        for &n in &[0, 9, 10, 255, 123_456_789, u64::MAX] {
+            assert_eq!(DisplayU64(n).to_string(), n.to_string());
+        }
+        assert_eq!(
+            format!(
+                "{:>5}|{:<5}|{:05}",
+                DisplayU64(42),
+                DisplayU64(7),
+                DisplayU64(3)
+            ),
+            "   42|7    |00003"
+        );
+    }
+
+    #[bench]
+    fn concat_vs_hardcoded_cow(b: &mut Bencher) {
         const CRACKLE: &str = "Crackle";
         const POP: &str = "Pop";
         const CRACKLE_POP: &str = "CracklePop";
@@ -729,42 +3536,42 @@ mod tests {
 
     #[bench]
     fn main_crackle_pop(b: &mut Bencher) {
-        b.iter(|| super::crackle_pop());
+        b.iter(super::crackle_pop);
     }
 
     #[bench]
     fn main_crackle_pop_hardcoded(b: &mut Bencher) {
-        b.iter(|| super::crackle_pop_hardcoded());
+        b.iter(super::crackle_pop_hardcoded);
     }
 
     #[bench]
     fn main_crackle_pop_faster_utf8(b: &mut Bencher) {
-        b.iter(|| super::crackle_pop_faster_utf8());
+        b.iter(super::crackle_pop_faster_utf8);
     }
 
     #[bench]
     fn main_crackle_pop_arrbuf(b: &mut Bencher) {
-        b.iter(|| super::crackle_pop_arrbuf());
+        b.iter(super::crackle_pop_arrbuf);
     }
 
     #[bench]
     fn main_crackle_pop_arraybuf_with_own_write_u8(b: &mut Bencher) {
-        b.iter(|| super::crackle_pop_arraybuf_with_own_write_u8());
+        b.iter(super::crackle_pop_arraybuf_with_own_write_u8);
     }
 
     #[bench]
     fn main_crackle_pop_arraybuf_with_newline_methods(b: &mut Bencher) {
-        b.iter(|| super::crackle_pop_arraybuf_with_newline_methods());
+        b.iter(super::crackle_pop_arraybuf_with_newline_methods);
     }
 
     #[bench]
     fn main_crackle_pop_arraybuf_minimal_vars(b: &mut Bencher) {
-        b.iter(|| super::crackle_pop_arraybuf_minimal_vars());
+        b.iter(super::crackle_pop_arraybuf_minimal_vars);
     }
 
     #[bench]
     fn main_crackle_pop_vec_minimal_vars(b: &mut Bencher) {
-        b.iter(|| super::crackle_pop_vec_minimal_vars());
+        b.iter(super::crackle_pop_vec_minimal_vars);
     }
 
     /*
@@ -868,6 +3675,293 @@ mod tests {
         });
     }
 
+    /*
+    The benches below measure what the ARRAY_BUFFER_SIZE docs describe: the
+    same minimal vars CracklePop into a freshly created buffer each iteration,
+    so the only thing varying between them is N. The gap grows with N, and
+    65536 lands an order of magnitude behind the rest:
+
+    test tests::buffer_size_512                                  ... bench:         574 ns/iter (+/- 132)
+    test tests::buffer_size_4096                                 ... bench:         616 ns/iter (+/- 122)
+    test tests::buffer_size_8192                                 ... bench:         704 ns/iter (+/- 140)
+    test tests::buffer_size_65536                                ... bench:       5,502 ns/iter (+/- 563)
+    */
+
+    /// `crackle_pop_arraybuf_minimal_vars` without the print, into a new
+    /// buffer of any size.
+    fn crackle_pop_into_new_arraybuf<const N: usize>() -> ArrayBuffer<u8, N> {
+        let mut buf: ArrayBuffer<u8, N> = ArrayBuffer::new();
+        for n in 1u8..=100 {
+            let div_by_3 = n % 3 == 0;
+            let div_by_5 = n % 5 == 0;
+
+            if div_by_3 && div_by_5 {
+                buf.push_buf_line(b"CracklePop");
+            } else if div_by_3 {
+                buf.push_buf_line(b"Crackle");
+            } else if div_by_5 {
+                buf.push_buf_line(b"Pop");
+            } else {
+                buf.write_u8_as_utf8_with_newline(n)
+            };
+        }
+        buf
+    }
+
+    macro_rules! buffer_size_benches {
+        ($($name:ident: $size:expr),* $(,)?) => {
+            $(
+                #[bench]
+                fn $name(b: &mut Bencher) {
+                    b.iter(|| test::black_box(crackle_pop_into_new_arraybuf::<$size>()));
+                }
+            )*
+        };
+    }
+
+    buffer_size_benches! {
+        buffer_size_512: 512,
+        buffer_size_4096: 4096,
+        buffer_size_8192: 8192,
+        buffer_size_65536: 65536,
+    }
+
+    #[test]
+    fn buffer_size_benches_write_the_same_output() {
+        let small = crackle_pop_into_new_arraybuf::<512>();
+        let large = crackle_pop_into_new_arraybuf::<65536>();
+        assert_eq!(small.pos, super::exact_bytes(1, 100));
+        assert_eq!(&small[..], &large[..]);
+    }
+
+    /*
+    The two benches below isolate the cost of the final flush, by flushing the
+    same fastest_arraybuf output through print! and through a raw write(2).
+    The raw one isn't captured by the harness and clobbers the terminal, so
+    it's ignored by default; run with `cargo bench -- --ignored stdout`.
+    */
+
+    #[bench]
+    fn flush_via_print(b: &mut Bencher) {
+        let mut buf: ArrayBuffer<u8, ARRAY_BUFFER_SIZE> = ArrayBuffer::new();
+        b.iter(|| {
+            super::crackle_pop_fastest_arraybuf(&mut buf);
+            buf.write_all_to_stdout().unwrap();
+        });
+    }
+
+    /*
+    Flushing into a Vec through Write vs write_all_to_vec. The difference is
+    within the noise, as Vec's Write impl is already an extend_from_slice and
+    the Vec keeps its capacity across iterations:
+
+    test tests::flush_to_vec_via_write                           ... bench:         379 ns/iter (+/- 74)
+    test tests::flush_to_vec_via_write_all_to_vec                ... bench:         375 ns/iter (+/- 41)
+    */
+
+    #[bench]
+    fn flush_to_vec_via_write(b: &mut Bencher) {
+        let mut buf: ArrayBuffer<u8, ARRAY_BUFFER_SIZE> = ArrayBuffer::new();
+        let mut vec = Vec::new();
+        b.iter(|| {
+            super::crackle_pop_fastest_arraybuf(&mut buf);
+            buf.write_all_to(&mut vec).unwrap();
+            vec.clear();
+        });
+    }
+
+    #[bench]
+    fn flush_to_vec_via_write_all_to_vec(b: &mut Bencher) {
+        let mut buf: ArrayBuffer<u8, ARRAY_BUFFER_SIZE> = ArrayBuffer::new();
+        let mut vec = Vec::new();
+        b.iter(|| {
+            super::crackle_pop_fastest_arraybuf(&mut buf);
+            buf.write_all_to_vec(&mut vec);
+            vec.clear();
+        });
+    }
+
+    #[cfg(unix)]
+    #[bench]
+    #[ignore]
+    fn flush_via_raw_stdout(b: &mut Bencher) {
+        let mut buf: ArrayBuffer<u8, ARRAY_BUFFER_SIZE> = ArrayBuffer::new();
+        b.iter(|| {
+            super::crackle_pop_fastest_arraybuf(&mut buf);
+            buf.write_all_to_stdout_raw().unwrap();
+        });
+    }
+
+    /*
+    print! against write_all_to on io::stdout(), which takes the lock on every
+    write, on a stdout locked once up front, and the raw write(2), with the Vec
+    flushes above as the no IO baseline. Like the raw one, the two new ones
+    clobber the terminal and are ignored; to reproduce, keep stdout out of the
+    way and read the numbers from a logfile:
+
+    cargo bench -- --nocapture --logfile print.log tests::flush_via_print >/dev/null
+    cargo bench -- --ignored --nocapture --logfile stdout.log tests::flush_via >/dev/null
+
+    With stdout going to /dev/null:
+
+    tests::flush_to_vec_via_write                                     210 ns/iter (+/- 17)
+    tests::flush_via_print                                            355 ns/iter (+/- 28)
+    tests::flush_via_raw_stdout                                       334 ns/iter (+/- 50)
+    tests::flush_via_stdout_locked                                    347 ns/iter (+/- 211)
+    tests::flush_via_stdout_unlocked                                  358 ns/iter (+/- 71)
+
+    So with a single flush of the whole buffer, the ~140ns over the Vec is
+    nearly all the syscall: the lock costs ~10ns and print!'s formatting
+    machinery about as much. The overhead quoted in the docs comes from
+    printing line by line, not from which of these does the one flush.
+    */
+
+    #[bench]
+    #[ignore]
+    fn flush_via_stdout_unlocked(b: &mut Bencher) {
+        let mut buf: ArrayBuffer<u8, ARRAY_BUFFER_SIZE> = ArrayBuffer::new();
+        b.iter(|| {
+            super::crackle_pop_fastest_arraybuf(&mut buf);
+            buf.write_all_to(&mut io::stdout()).unwrap();
+        });
+    }
+
+    #[bench]
+    #[ignore]
+    fn flush_via_stdout_locked(b: &mut Bencher) {
+        let mut buf: ArrayBuffer<u8, ARRAY_BUFFER_SIZE> = ArrayBuffer::new();
+        let stdout = io::stdout();
+        let mut out = stdout.lock();
+        b.iter(|| {
+            super::crackle_pop_fastest_arraybuf(&mut buf);
+            buf.write_all_to(&mut out).unwrap();
+        });
+    }
+
+    /*
+    insert and drain shift the tail of the buffer with copy_within. The loop
+    versions below do the same element by element, to check that it pays off:
+    each iteration inserts a line near the front of a nearly full 4 KiB buffer
+    and drains it again, shifting ~4000 bytes each way. The loops don't get
+    turned into a memmove, and are well over an order of magnitude slower:
+
+    test tests::edit_mid_buffer_via_copy_within                  ... bench:          73 ns/iter (+/- 16)
+    test tests::edit_mid_buffer_via_loop                         ... bench:       4,919 ns/iter (+/- 744)
+    */
+
+    /// `ArrayBuffer::insert`, shifting one byte at a time.
+    fn insert_via_loop<const N: usize>(ab: &mut ArrayBuffer<u8, N>, at: usize, items: &[u8]) {
+        assert!(at <= ab.pos && items.len() <= N - ab.pos);
+        for i in (at..ab.pos).rev() {
+            ab.buf[i + items.len()] = ab.buf[i];
+        }
+        for (i, &item) in items.iter().enumerate() {
+            ab.buf[at + i] = item;
+        }
+        ab.pos += items.len();
+    }
+
+    /// `ArrayBuffer::drain`, shifting one byte at a time.
+    fn drain_via_loop<const N: usize>(ab: &mut ArrayBuffer<u8, N>, range: std::ops::Range<usize>) {
+        assert!(range.start <= range.end && range.end <= ab.pos);
+        for i in range.end..ab.pos {
+            ab.buf[i - range.len()] = ab.buf[i];
+        }
+        ab.pos -= range.len();
+    }
+
+    fn nearly_full_buffer() -> ArrayBuffer<u8, 4096> {
+        let mut ab = ArrayBuffer::new();
+        ab.fill_with_pattern(crackle_pop_into_new_arraybuf::<512>().as_slice());
+        ab.drain(4000..4096);
+        ab
+    }
+
+    const EDIT_AT: usize = 100;
+    const EDIT_LINE: &[u8] = b"Crackle\n";
+
+    #[test]
+    fn loop_edits_match_copy_within_edits() {
+        let mut fast = nearly_full_buffer();
+        let mut slow = nearly_full_buffer();
+        fast.insert(EDIT_AT, EDIT_LINE);
+        insert_via_loop(&mut slow, EDIT_AT, EDIT_LINE);
+        assert_eq!(fast.as_slice(), slow.as_slice());
+
+        fast.drain(10..EDIT_AT + 3);
+        drain_via_loop(&mut slow, 10..EDIT_AT + 3);
+        assert_eq!(fast.as_slice(), slow.as_slice());
+    }
+
+    #[bench]
+    fn edit_mid_buffer_via_copy_within(b: &mut Bencher) {
+        let mut ab = nearly_full_buffer();
+        b.iter(|| {
+            ab.insert(EDIT_AT, EDIT_LINE);
+            ab.drain(EDIT_AT..EDIT_AT + EDIT_LINE.len());
+            test::black_box(&ab);
+        });
+    }
+
+    #[bench]
+    fn edit_mid_buffer_via_loop(b: &mut Bencher) {
+        let mut ab = nearly_full_buffer();
+        b.iter(|| {
+            insert_via_loop(&mut ab, EDIT_AT, EDIT_LINE);
+            drain_via_loop(&mut ab, EDIT_AT..EDIT_AT + EDIT_LINE.len());
+            test::black_box(&ab);
+        });
+    }
+
+    #[test]
+    fn append_display_matches_display() {
+        let mut ab: ArrayBuffer<u8, 256> = ArrayBuffer::new();
+        ab.append_display(&0u8);
+        ab.append_display(&u64::MAX);
+        ab.append_display(&i8::MIN);
+        ab.append_display(&-7i64);
+        ab.append_display(&i64::MIN);
+        ab.append_display(" Crackle ");
+        ab.append_display(&'é');
+        ab.append_display(&1.5f64);
+        ab.append_display(&super::DisplayU64(42));
+        let expected = format!("0{}{}-7{} Crackle é1.542", u64::MAX, i8::MIN, i64::MIN);
+        assert!(ab.eq_str(&expected));
+    }
+
+    /*
+    Integers through append_display's encoder against write!, both into an
+    ArrayBuffer. A smaller win than for u8s, as format_u64_into still divides
+    its way through every digit:
+
+    test tests::append_display_ints                              ... bench:       9,794 ns/iter (+/- 2,481)
+    test tests::append_ints_via_write                            ... bench:      13,412 ns/iter (+/- 1,740)
+    */
+
+    #[bench]
+    fn append_display_ints(b: &mut Bencher) {
+        let mut ab: ArrayBuffer<u8, 4096> = ArrayBuffer::new();
+        b.iter(|| {
+            for i in 0u32..1000 {
+                ab.append_display(&i);
+            }
+            test::black_box(&ab);
+            ab.clear();
+        });
+    }
+
+    #[bench]
+    fn append_ints_via_write(b: &mut Bencher) {
+        let mut ab: ArrayBuffer<u8, 4096> = ArrayBuffer::new();
+        b.iter(|| {
+            for i in 0u32..1000 {
+                write!(ab, "{}", i).unwrap();
+            }
+            test::black_box(&ab);
+            ab.clear();
+        });
+    }
+
     #[bench]
     fn num_via_vec_write(b: &mut Bencher) {
         let mut vec = Vec::with_capacity(10000);
@@ -891,6 +3985,36 @@ mod tests {
         });
     }
 
+    #[bench]
+    fn num_via_str_write_display_u64(b: &mut Bencher) {
+        use std::fmt::Write;
+        let mut buf = String::with_capacity(10000);
+        b.iter(|| {
+            buf.clear();
+            for i in 0u64..100 {
+                write!(buf, "{}", super::DisplayU64(i)).unwrap();
+            }
+        });
+    }
+
+    #[bench]
+    fn num_via_format(b: &mut Bencher) {
+        b.iter(|| {
+            for i in 0u64..100 {
+                test::black_box(format!("{}", i));
+            }
+        });
+    }
+
+    #[bench]
+    fn num_via_format_display_u64(b: &mut Bencher) {
+        b.iter(|| {
+            for i in 0u64..100 {
+                test::black_box(format!("{}", super::DisplayU64(i)));
+            }
+        });
+    }
+
     // #[bench]
     // fn num_via_str_write(b: &mut Bencher) {
     //     use std::fmt::Write;
@@ -916,8 +4040,9 @@ mod tests {
         });
     }
 
-    // According to benchmarks this performs literally about 20 times worse than
-    // when handling values beneath 100.
+    // According to benchmarks this used to perform literally about 20 times
+    // worse than when handling values beneath 100. See the branchy benches
+    // below for how the table lookup fixed that.
     #[bench]
     fn write_u8_gt_100(b: &mut Bencher) {
         let vec = &mut Vec::with_capacity(1000);
@@ -929,6 +4054,84 @@ mod tests {
         });
     }
 
+    /*
+    The same two benches through the old branching writer, for comparison with
+    the U8_DECIMAL lookup:
+
+    test tests::write_u8_lt_100                                  ... bench:         282 ns/iter (+/- 13)
+    test tests::write_u8_lt_100_branchy                          ... bench:         378 ns/iter (+/- 190)
+    test tests::write_u8_gt_100                                  ... bench:         258 ns/iter (+/- 191)
+    test tests::write_u8_gt_100_branchy                          ... bench:       4,297 ns/iter (+/- 3,058)
+    */
+
+    #[bench]
+    fn write_u8_lt_100_branchy(b: &mut Bencher) {
+        let vec = &mut Vec::with_capacity(1000);
+        b.iter(|| {
+            for i in 0..100 {
+                super::write_u8_as_utf8_branchy(i, vec);
+            }
+            vec.clear();
+        });
+    }
+
+    #[bench]
+    fn write_u8_gt_100_branchy(b: &mut Bencher) {
+        let vec = &mut Vec::with_capacity(1000);
+        b.iter(|| {
+            for i in 100..200 {
+                super::write_u8_as_utf8_branchy(i, vec);
+            }
+            vec.clear();
+        });
+    }
+
+    #[test]
+    fn write_u8_as_utf8_with_sep_matches_to_string() {
+        let mut ab: ArrayBuffer<u8, 1024> = ArrayBuffer::new();
+        let mut expected = String::new();
+        for x in 0..=255 {
+            ab.write_u8_as_utf8_with_sep(x, b',');
+            expected += &format!("{},", x);
+        }
+        assert!(ab.eq_str(&expected));
+    }
+
+    /*
+    A number and its separator in one fused push_fixed, against pushing the
+    same U8_DECIMAL digits and then the separator, over every u8. Fusing still
+    wins, by about 2x:
+
+    test tests::write_u8_with_sep_fused                          ... bench:         359 ns/iter (+/- 50)
+    test tests::write_u8_with_sep_separate_push                  ... bench:         796 ns/iter (+/- 473)
+    */
+
+    #[bench]
+    fn write_u8_with_sep_fused(b: &mut Bencher) {
+        let mut ab: ArrayBuffer<u8, 1024> = ArrayBuffer::new();
+        b.iter(|| {
+            for x in 0..=255 {
+                ab.write_u8_as_utf8_with_sep(x, b',');
+            }
+            test::black_box(&ab);
+            ab.clear();
+        });
+    }
+
+    #[bench]
+    fn write_u8_with_sep_separate_push(b: &mut Bencher) {
+        let mut ab: ArrayBuffer<u8, 1024> = ArrayBuffer::new();
+        b.iter(|| {
+            for x in 0..=255 {
+                let (digits, len) = &super::U8_DECIMAL[x as usize];
+                ab.push_buf(&digits[..*len as usize]);
+                ab.push(b',');
+            }
+            test::black_box(&ab);
+            ab.clear();
+        });
+    }
+
     /// This test shows that writing directly to stdout is not captured in tests
     /// unlike println! is...
     #[test]
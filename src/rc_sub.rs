@@ -12,7 +12,13 @@ pub fn main() {
 /// Conservatively give more than enough byte space, so that we only need 1 allocation.
 const CAPACITY: usize = "CracklePop".len() * 100;
 
+/// Both solutions end with a newline after the last line, like every variant
+/// in the main module.
 fn crackle_pop() {
+    print!("{}", render());
+}
+
+fn render() -> String {
     let mut str = String::with_capacity(CAPACITY);
     for n in 1..=100 {
         let div_by_3 = n % 3 == 0;
@@ -29,12 +35,17 @@ fn crackle_pop() {
         }
         str.push('\n');
     }
-    print!("{}", str.trim());
+    str
 }
 
 /// About 7x faster than the simpler implementation.
 #[allow(unused)]
 fn crackle_pop_fast() {
+    // Safe because of testing write_1_or_2_digit_u8_as_utf8 with 0 to 99.
+    print!("{}", unsafe { String::from_utf8_unchecked(render_fast()) });
+}
+
+fn render_fast() -> Vec<u8> {
     let mut buf = Vec::with_capacity(CAPACITY);
     const CRACKLE: &[u8] = b"Crackle";
     const POP: &[u8] = b"Pop";
@@ -55,9 +66,7 @@ fn crackle_pop_fast() {
         };
         buf.push(b'\n');
     }
-
-    // Safe because of testing write_1_or_2_digit_u8_as_utf8 with 0 to 99.
-    print!("{}", unsafe { String::from_utf8_unchecked(buf).trim() });
+    buf
 }
 
 use std::io::Write;
@@ -92,15 +101,25 @@ mod tests {
         }
     }
 
+    #[test]
+    fn matches_the_main_module() {
+        let expected = crate::crackle_pop_string();
+        assert_eq!(render(), expected);
+        assert_eq!(String::from_utf8(render_fast()).unwrap(), expected);
+
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/crackle_pop_1_100.txt");
+        assert_eq!(expected, std::fs::read_to_string(path).unwrap());
+    }
+
     #[bench]
     // test rc_sub::tests::normal                                   ... bench:       4,414 ns/iter (+/- 216)
     fn normal(b: &mut Bencher) {
-        b.iter(|| crackle_pop());
+        b.iter(crackle_pop);
     }
 
     #[bench]
     // test rc_sub::tests::fast                                     ... bench:         618 ns/iter (+/- 88)
     fn fast(b: &mut Bencher) {
-        b.iter(|| crackle_pop_fast());
+        b.iter(crackle_pop_fast);
     }
 }
@@ -0,0 +1,213 @@
+//! Classifies numbers 16 at a time with SIMD, as a companion to the digit
+//! batching idea in `_crackle_pop_split_up` but applied to the divisibility
+//! checks instead.
+//!
+//! There's no division involved: each lane keeps its number's remainder mod 3
+//! and mod 5, and comparing those against zero yields the Crackle and Pop
+//! masks. Moving on to the next 16 numbers adds 16 to every lane, and since
+//! 16 is 1 mod 3 and 1 mod 5, that's just adding 1 to every remainder and
+//! wrapping the lanes that reached 3 (or 5) back to 0.
+//!
+//! Which instructions get used is decided at runtime, so a build runs on any
+//! CPU: AVX2 where `is_x86_feature_detected!` finds it, 32 numbers at a time,
+//! otherwise SSE2, which every x86_64 CPU has, and the scalar path anywhere
+//! else.
+
+use std::io::{self, Write};
+
+use crate::engine::Category;
+
+/// The categories of every number in `start..=end`, through the fastest path
+/// this CPU supports.
+pub fn classify_range_simd(start: u64, end: u64) -> Vec<Category> {
+    #[cfg(target_arch = "x86_64")]
+    {
+        if is_x86_feature_detected!("avx2") {
+            // Safe, as we've just checked AVX2 is there.
+            return unsafe { classify_range_avx2(start, end) };
+        }
+        classify_range_sse2(start, end)
+    }
+    #[cfg(not(target_arch = "x86_64"))]
+    classify_range_scalar(start, end)
+}
+
+/// Classic CracklePop over `start..=end` into `out`, classified by
+/// `classify_range_simd`. Safe to call on any CPU.
+pub fn crackle_pop_simd<W: Write>(start: u64, end: u64, out: &mut W) -> io::Result<()> {
+    for (n, category) in (start..).zip(classify_range_simd(start, end)) {
+        category.render(n, out)?;
+        out.write_all(b"\n")?;
+    }
+    Ok(())
+}
+
+#[cfg(target_arch = "x86_64")]
+fn classify_range_sse2(start: u64, end: u64) -> Vec<Category> {
+    use std::arch::x86_64::*;
+
+    if start > end {
+        return Vec::new();
+    }
+    let len = (end - start + 1) as usize;
+    let mut out: Vec<Category> = Vec::with_capacity(len);
+
+    let mut rem_3 = [0u8; 16];
+    let mut rem_5 = [0u8; 16];
+    for i in 0..16 {
+        rem_3[i] = ((start % 3 + i as u64) % 3) as u8;
+        rem_5[i] = ((start % 5 + i as u64) % 5) as u8;
+    }
+
+    let mut done = 0;
+    // SSE2 is part of the x86_64 baseline, so these are always available.
+    unsafe {
+        let zero = _mm_setzero_si128();
+        let one = _mm_set1_epi8(1);
+        let two = _mm_set1_epi8(2);
+        let three = _mm_set1_epi8(3);
+        let five = _mm_set1_epi8(5);
+        let mut rem_3 = _mm_loadu_si128(rem_3.as_ptr() as *const __m128i);
+        let mut rem_5 = _mm_loadu_si128(rem_5.as_ptr() as *const __m128i);
+
+        while done + 16 <= len {
+            let crackle = _mm_and_si128(_mm_cmpeq_epi8(rem_3, zero), one);
+            let pop = _mm_and_si128(_mm_cmpeq_epi8(rem_5, zero), two);
+            // Category is repr(u8), and every lane holds 0..=3, a valid
+            // discriminant.
+            let dst = out.as_mut_ptr().add(done) as *mut __m128i;
+            _mm_storeu_si128(dst, _mm_or_si128(crackle, pop));
+            done += 16;
+
+            rem_3 = _mm_add_epi8(rem_3, one);
+            rem_3 = _mm_sub_epi8(rem_3, _mm_and_si128(_mm_cmpeq_epi8(rem_3, three), three));
+            rem_5 = _mm_add_epi8(rem_5, one);
+            rem_5 = _mm_sub_epi8(rem_5, _mm_and_si128(_mm_cmpeq_epi8(rem_5, five), five));
+        }
+        out.set_len(done);
+    }
+
+    // Whatever doesn't fill a whole vector goes through the scalar path.
+    out.extend((start + done as u64..=end).map(Category::of));
+    out
+}
+
+/// `classify_range_sse2` twice as wide. 32 is 2 mod 3 and 2 mod 5, so moving
+/// on adds 2 to every remainder instead, and wraps the ones past 2 (or 4).
+///
+/// Only safe to call if the CPU supports AVX2.
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx2")]
+unsafe fn classify_range_avx2(start: u64, end: u64) -> Vec<Category> {
+    use std::arch::x86_64::*;
+
+    if start > end {
+        return Vec::new();
+    }
+    let len = (end - start + 1) as usize;
+    let mut out: Vec<Category> = Vec::with_capacity(len);
+
+    let mut rem_3 = [0u8; 32];
+    let mut rem_5 = [0u8; 32];
+    for i in 0..32 {
+        rem_3[i] = ((start % 3 + i as u64) % 3) as u8;
+        rem_5[i] = ((start % 5 + i as u64) % 5) as u8;
+    }
+
+    let zero = _mm256_setzero_si256();
+    let one = _mm256_set1_epi8(1);
+    let two = _mm256_set1_epi8(2);
+    let three = _mm256_set1_epi8(3);
+    let four = _mm256_set1_epi8(4);
+    let five = _mm256_set1_epi8(5);
+    let mut rem_3 = _mm256_loadu_si256(rem_3.as_ptr() as *const __m256i);
+    let mut rem_5 = _mm256_loadu_si256(rem_5.as_ptr() as *const __m256i);
+
+    let mut done = 0;
+    while done + 32 <= len {
+        let crackle = _mm256_and_si256(_mm256_cmpeq_epi8(rem_3, zero), one);
+        let pop = _mm256_and_si256(_mm256_cmpeq_epi8(rem_5, zero), two);
+        // As for SSE2, every lane holds a valid Category discriminant.
+        let dst = out.as_mut_ptr().add(done) as *mut __m256i;
+        _mm256_storeu_si256(dst, _mm256_or_si256(crackle, pop));
+        done += 32;
+
+        rem_3 = _mm256_add_epi8(rem_3, two);
+        rem_3 = _mm256_sub_epi8(
+            rem_3,
+            _mm256_and_si256(_mm256_cmpgt_epi8(rem_3, two), three),
+        );
+        rem_5 = _mm256_add_epi8(rem_5, two);
+        rem_5 = _mm256_sub_epi8(
+            rem_5,
+            _mm256_and_si256(_mm256_cmpgt_epi8(rem_5, four), five),
+        );
+    }
+    out.set_len(done);
+
+    out.extend((start + done as u64..=end).map(Category::of));
+    out
+}
+
+/// The per-number branching version, for comparison.
+pub fn classify_range_scalar(start: u64, end: u64) -> Vec<Category> {
+    if start > end {
+        return Vec::new();
+    }
+    (start..=end).map(Category::of).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate test;
+    use super::*;
+    use test::Bencher;
+
+    const RANGES: &[(u64, u64)] = &[(1, 100), (0, 15), (7, 7), (3, 40), (999, 1234), (5, 4)];
+
+    #[test]
+    fn simd_matches_scalar() {
+        for &(start, end) in RANGES {
+            assert_eq!(
+                classify_range_simd(start, end),
+                classify_range_scalar(start, end),
+                "{}..={}",
+                start,
+                end
+            );
+        }
+    }
+
+    /// Every path directly, whichever one the dispatch picks on this machine.
+    #[cfg(target_arch = "x86_64")]
+    #[test]
+    fn every_path_matches_scalar() {
+        for &(start, end) in RANGES {
+            let scalar = classify_range_scalar(start, end);
+            assert_eq!(classify_range_sse2(start, end), scalar);
+            if is_x86_feature_detected!("avx2") {
+                assert_eq!(unsafe { classify_range_avx2(start, end) }, scalar);
+            }
+        }
+    }
+
+    #[test]
+    fn crackle_pop_simd_matches_engine() {
+        for &(start, end) in RANGES {
+            let (mut simd, mut engine) = (Vec::new(), Vec::new());
+            crackle_pop_simd(start, end, &mut simd).unwrap();
+            crate::engine::crackle_pop_into(start..=end, &mut engine, true).unwrap();
+            assert_eq!(simd, engine, "{}..={}", start, end);
+        }
+    }
+
+    #[bench]
+    fn classify_simd(b: &mut Bencher) {
+        b.iter(|| classify_range_simd(1, 10_000));
+    }
+
+    #[bench]
+    fn classify_scalar(b: &mut Bencher) {
+        b.iter(|| classify_range_scalar(1, 10_000));
+    }
+}
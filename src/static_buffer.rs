@@ -0,0 +1,60 @@
+//! A read-only counterpart to `ArrayBuffer` for output that's known at compile
+//! time, like a pre-rendered CracklePop: it wraps a `&'static [u8]` instead of
+//! owning an array, so there's nothing to copy in before flushing it.
+//!
+//! Writing isn't supported at all. There's no array to write into, and no
+//! `push` methods or `Write` impl to do it with; build an `ArrayBuffer` for
+//! output that's only known at runtime.
+
+use std::io::{self, Write};
+
+#[derive(Debug, Clone, Copy)]
+pub struct StaticBuffer {
+    bytes: &'static [u8],
+}
+
+impl StaticBuffer {
+    pub const fn new(bytes: &'static [u8]) -> Self {
+        StaticBuffer { bytes }
+    }
+
+    pub fn as_slice(&self) -> &'static [u8] {
+        self.bytes
+    }
+
+    pub fn len(&self) -> usize {
+        self.bytes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+
+    /// Writes all the bytes to `out`, retrying `Interrupted` writes, like
+    /// `ArrayBuffer::write_all_to`. Unlike it, nothing gets emptied: the bytes
+    /// are static, so every flush writes all of them again.
+    pub fn write_all_to<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        crate::write_all_retrying(out, self.bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GOLDEN: StaticBuffer =
+        StaticBuffer::new(include_bytes!("../tests/crackle_pop_1_100.txt"));
+
+    #[test]
+    fn flushes_the_same_bytes_every_time() {
+        let mut expected = Vec::new();
+        crate::engine::crackle_pop_into(1..=100, &mut expected, true).unwrap();
+        assert_eq!(GOLDEN.len(), expected.len());
+
+        let mut out = Vec::new();
+        GOLDEN.write_all_to(&mut out).unwrap();
+        GOLDEN.write_all_to(&mut out).unwrap();
+        assert_eq!(out, [&expected[..], &expected[..]].concat());
+        assert!(StaticBuffer::new(b"").is_empty());
+    }
+}
@@ -0,0 +1,243 @@
+//! The `bench` subcommand: a stable Rust approximation of the nightly
+//! `#[bench]` numbers quoted throughout the docs, timed with `Instant`.
+//!
+//! Run with `crackle_pop bench [--iters N] [--impl NAME]`. Several of the
+//! implementations print their output, so the table goes to stderr; redirect
+//! stdout to /dev/null to keep it readable.
+
+use std::hint::black_box;
+use std::process;
+use std::time::Instant;
+
+use crate::engine::{self, Config};
+use crate::{ArrayBuffer, ARRAY_BUFFER_SIZE};
+
+const DEFAULT_ITERS: u32 = 10_000;
+
+#[derive(Debug, PartialEq)]
+struct Options {
+    iters: u32,
+    /// Only time the implementation with this name.
+    only: Option<String>,
+}
+
+fn parse_args(args: &[String]) -> Result<Options, String> {
+    let mut options = Options {
+        iters: DEFAULT_ITERS,
+        only: None,
+    };
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--iters" => {
+                let value = args.next().ok_or("--iters needs a value")?;
+                options.iters = value
+                    .parse()
+                    .map_err(|_| format!("invalid --iters value: {}", value))?;
+                if options.iters == 0 {
+                    return Err("--iters must be at least 1".to_string());
+                }
+            }
+            "--impl" => {
+                let value = args.next().ok_or("--impl needs a value")?;
+                options.only = Some(value.clone());
+            }
+            other => return Err(format!("unknown argument: {}", other)),
+        }
+    }
+    Ok(options)
+}
+
+type Implementation = (&'static str, Box<dyn FnMut()>);
+
+/// Every public implementation. The ones writing into a buffer own it and
+/// reset it after each run, just like the `#[bench]` versions.
+fn implementations() -> Vec<Implementation> {
+    let mut ext_arraybuf: ArrayBuffer<u8, ARRAY_BUFFER_SIZE> = ArrayBuffer::new();
+    let mut ext_owned_arraybuf: ArrayBuffer<u8, ARRAY_BUFFER_SIZE> = ArrayBuffer::new();
+    let mut fastest_arraybuf: ArrayBuffer<u8, ARRAY_BUFFER_SIZE> = ArrayBuffer::new();
+    let mut ext_vec = Vec::with_capacity(ARRAY_BUFFER_SIZE);
+    let mut fastest_vec = Vec::with_capacity(ARRAY_BUFFER_SIZE);
+    let mut engine_vec = Vec::with_capacity(ARRAY_BUFFER_SIZE);
+    let mut config_vec = Vec::with_capacity(ARRAY_BUFFER_SIZE);
+    let config = Config::default();
+
+    vec![
+        ("crackle_pop", Box::new(crate::crackle_pop)),
+        (
+            "crackle_pop_hardcoded",
+            Box::new(crate::crackle_pop_hardcoded),
+        ),
+        (
+            "crackle_pop_faster_utf8",
+            Box::new(crate::crackle_pop_faster_utf8),
+        ),
+        ("crackle_pop_arrbuf", Box::new(crate::crackle_pop_arrbuf)),
+        (
+            "crackle_pop_arraybuf_with_own_write_u8",
+            Box::new(crate::crackle_pop_arraybuf_with_own_write_u8),
+        ),
+        (
+            "crackle_pop_arraybuf_with_newline_methods",
+            Box::new(crate::crackle_pop_arraybuf_with_newline_methods),
+        ),
+        (
+            "crackle_pop_arraybuf_minimal_vars",
+            Box::new(crate::crackle_pop_arraybuf_minimal_vars),
+        ),
+        (
+            "crackle_pop_vec_minimal_vars",
+            Box::new(crate::crackle_pop_vec_minimal_vars),
+        ),
+        (
+            "crackle_pop_ext_arraybuf_minimal_vars",
+            Box::new(move || {
+                let mut buf = ext_arraybuf.scope();
+                crate::crackle_pop_ext_arraybuf_minimal_vars(&mut buf);
+                black_box(&*buf);
+            }),
+        ),
+        (
+            "crackle_pop_ext_owned_arraybuf_minimal_vars",
+            Box::new(move || {
+                ext_owned_arraybuf =
+                    crate::crackle_pop_ext_owned_arraybuf_minimal_vars(ext_owned_arraybuf.clone());
+                black_box(&ext_owned_arraybuf);
+                ext_owned_arraybuf.pos = 0;
+            }),
+        ),
+        (
+            "crackle_pop_ext_vec_minimal_vars",
+            Box::new(move || {
+                crate::crackle_pop_ext_vec_minimal_vars(&mut ext_vec);
+                black_box(&ext_vec);
+                ext_vec.clear();
+            }),
+        ),
+        (
+            "crackle_pop_fastest_vec",
+            Box::new(move || {
+                crate::crackle_pop_fastest_vec(&mut fastest_vec);
+                black_box(&fastest_vec);
+                fastest_vec.clear();
+            }),
+        ),
+        (
+            "crackle_pop_fastest_arraybuf",
+            Box::new(move || {
+                let mut buf = fastest_arraybuf.scope();
+                crate::crackle_pop_fastest_arraybuf(&mut buf);
+                black_box(&*buf);
+            }),
+        ),
+        (
+            "engine::crackle_pop_into",
+            Box::new(move || {
+                engine::crackle_pop_into(1..=100, &mut engine_vec, true).unwrap();
+                black_box(&engine_vec);
+                engine_vec.clear();
+            }),
+        ),
+        (
+            "engine::Config::run_into",
+            Box::new(move || {
+                config.run_into(&mut config_vec).unwrap();
+                black_box(&config_vec);
+                config_vec.clear();
+            }),
+        ),
+    ]
+}
+
+pub fn main(args: &[String]) {
+    let options = match parse_args(args) {
+        Ok(options) => options,
+        Err(err) => {
+            eprintln!("{}", err);
+            eprintln!("usage: crackle_pop bench [--iters N] [--impl NAME]");
+            process::exit(2);
+        }
+    };
+
+    let mut implementations = implementations();
+    if let Some(only) = &options.only {
+        implementations.retain(|(name, _)| name == only);
+        if implementations.is_empty() {
+            eprintln!("unknown implementation: {}. Available:", only);
+            for (name, _) in self::implementations() {
+                eprintln!("  {}", name);
+            }
+            process::exit(2);
+        }
+    }
+
+    let mut results = Vec::with_capacity(implementations.len());
+    for (name, run) in &mut implementations {
+        // One warm up run, so the first iteration doesn't pay for page faults.
+        run();
+        let start = Instant::now();
+        for _ in 0..options.iters {
+            run();
+        }
+        let ns_per_iter = start.elapsed().as_nanos() / u128::from(options.iters);
+        results.push((*name, ns_per_iter));
+    }
+
+    let slowest = results.iter().map(|&(_, ns)| ns).max().unwrap_or(1).max(1);
+    eprintln!(
+        "{:<45} {:>12} {:>10}",
+        "implementation", "ns/iter", "speedup"
+    );
+    for (name, ns) in results {
+        let speedup = slowest as f64 / ns.max(1) as f64;
+        eprintln!("{:<45} {:>12} {:>9.2}x", name, ns, speedup);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn parses_iters_and_impl() {
+        assert_eq!(
+            parse_args(&args(&[])),
+            Ok(Options {
+                iters: DEFAULT_ITERS,
+                only: None
+            })
+        );
+        assert_eq!(
+            parse_args(&args(&["--iters", "5", "--impl", "crackle_pop"])),
+            Ok(Options {
+                iters: 5,
+                only: Some("crackle_pop".to_string())
+            })
+        );
+    }
+
+    #[test]
+    fn rejects_bad_args() {
+        assert!(parse_args(&args(&["--iters"])).is_err());
+        assert!(parse_args(&args(&["--iters", "lots"])).is_err());
+        assert!(parse_args(&args(&["--iters", "0"])).is_err());
+        assert!(parse_args(&args(&["--fast"])).is_err());
+    }
+
+    #[test]
+    fn implementation_names_are_unique() {
+        let mut names: Vec<_> = implementations()
+            .into_iter()
+            .map(|(name, _)| name)
+            .collect();
+        let len = names.len();
+        names.sort_unstable();
+        names.dedup();
+        assert_eq!(names.len(), len);
+    }
+}
@@ -0,0 +1,67 @@
+//! Optional `tracing` instrumentation around the `ArrayBuffer` flushes, for
+//! embedding the buffer in a larger observable service. Enabled with the
+//! `tracing` feature; without it `flush` is just a call to the closure, so the
+//! benchmarks are unaffected.
+
+use std::io;
+
+/// A flush of a buffer that's at least this full (in percent) when flushed
+/// gets a warning, since it was close to overflowing and likely wants to be
+/// flushed more often or made bigger.
+#[cfg(feature = "tracing")]
+const NEAR_FULL_PERCENT: usize = 90;
+
+/// Runs `flush`, which writes out `bytes` of a buffer of size `capacity` to
+/// `sink`, inside a span recording how much was flushed and how long it took.
+#[cfg(feature = "tracing")]
+pub(crate) fn flush<F>(
+    sink: &'static str,
+    bytes: usize,
+    capacity: usize,
+    flush: F,
+) -> io::Result<()>
+where
+    F: FnOnce() -> io::Result<()>,
+{
+    use std::time::Instant;
+
+    let _span = tracing::debug_span!("flush", sink, bytes, capacity).entered();
+    if bytes * 100 >= capacity * NEAR_FULL_PERCENT {
+        tracing::warn!(bytes, capacity, "buffer was near full when flushed");
+    }
+
+    let start = Instant::now();
+    let result = flush();
+    let elapsed_ns = start.elapsed().as_nanos() as u64;
+    match &result {
+        Ok(()) => tracing::debug!(elapsed_ns, "flushed"),
+        Err(err) => tracing::error!(elapsed_ns, error = %err, "flush failed"),
+    }
+    result
+}
+
+#[cfg(not(feature = "tracing"))]
+#[inline(always)]
+pub(crate) fn flush<F>(
+    _sink: &'static str,
+    _bytes: usize,
+    _capacity: usize,
+    flush: F,
+) -> io::Result<()>
+where
+    F: FnOnce() -> io::Result<()>,
+{
+    flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn flush_passes_the_result_through() {
+        assert!(flush("test", 1, 2, || Ok(())).is_ok());
+        let err = flush("test", 2, 2, || Err(io::Error::other("full"))).unwrap_err();
+        assert_eq!(err.to_string(), "full");
+    }
+}